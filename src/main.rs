@@ -1,10 +1,175 @@
-use colored::Colorize;
+use colored::{Color, Colorize};
 use std::env;
-use std::fs::File;
-use std::io::{self, BufRead, BufReader};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, IsTerminal, Read, Write};
 use std::path::{Path, PathBuf};
+use std::process::{self, Child, Command, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 use walkdir::WalkDir;
 
+// Backend used to find the pattern in a line
+#[derive(Debug)]
+enum SearchEngine {
+    // Plain byte-by-byte substring search
+    Literal,
+    // POSIX basic regular expression (-G), translated to the regex crate syntax
+    BasicRegexp(regex::Regex),
+    // POSIX extended regular expression (-E), the regex crate syntax is a superset of ERE
+    ExtendedRegexp(regex::Regex),
+    // A -G or -E regex with --capture, only the named group of each match is reported
+    CaptureRegexp {
+        compiled_regex: regex::Regex,
+        capture_group: String,
+    },
+    // Approximate search (--fuzzy), substrings within a Levenshtein distance of the pattern
+    Fuzzy { max_distance: usize },
+    // Phonetic search (--soundex), words with the same American Soundex code as the pattern
+    Soundex { pattern_code: String },
+    // Word similarity search (--similarity-threshold), words scoring at least threshold against the pattern
+    Similarity { similarity_metric: SimilarityMetric, threshold: f64 },
+    // Any word of a --word-list file as a whole word, all words are searched in a single pass
+    WordList(aho_corasick::AhoCorasick),
+    // The pattern as it looks inside base64 text (--base64), one form for each alignment
+    Base64(aho_corasick::AhoCorasick),
+    // Raw byte sequence given in hex (--hex-pattern), compared with the bytes of the line as read
+    HexBytes(Vec<u8>),
+    // Perl-compatible regular expression, compiled once in parse_arguments (--pcre2)
+    // With --capture, only the named group of each match is reported
+    #[cfg(feature = "pcre2")]
+    Pcre2 {
+        pcre2_regex: pcre2::bytes::Regex,
+        capture_group: Option<String>,
+    },
+}
+
+// String similarity used by --similarity-metric, all scores are between 0.0 and 1.0
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SimilarityMetric {
+    Levenshtein,
+    Jaro,
+    // prefix_scale is how much each character of a common prefix of up to max_prefix_length characters
+    // raises the Jaro score
+    JaroWinkler { prefix_scale: f64, max_prefix_length: usize },
+    // Only compares words of the same length as the pattern, e.g. fixed-width record fields
+    Hamming,
+}
+
+// A kind of file that --type can select
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FileKind {
+    Regular,
+    Directory,
+    Symlink,
+    BlockDevice,
+    CharDevice,
+    Fifo,
+    Socket,
+}
+
+// Permission check of --permission MODE, on the lower 12 bits of the Unix mode
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PermissionFilter {
+    // Octal notation (0644): the permissions have to be exactly these
+    Exact(u32),
+    // Symbolic notation (u+r,o-w): these bits have to be set, and those cleared
+    Symbolic { set_bits: u32, unset_bits: u32 },
+}
+
+// Ways of searching that take the place of the literal search, only one of them can be used
+// and none goes together with a regex (see has_other_pattern_kind)
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PatternKind {
+    Base64,
+    HexBytes,
+    WordList,
+    Similarity,
+    Soundex,
+    Fuzzy,
+}
+
+// How the pattern given on the command line is interpreted
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PatternSyntax {
+    Literal,
+    Basic,
+    Extended,
+    Perl,
+}
+
+// What to do with a directory named on the command line (--directory=MODE)
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DirectoryMode {
+    // Try to read it like a file, which reports an error
+    Read,
+    // Leave it out (unless -r is given)
+    Skip,
+    // Search it recursively, as if -r was given for this argument
+    Recurse,
+}
+
+// Order in which buffered results are printed (--sort-output-by)
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SortBy {
+    // Alphabetically by file name, keeping line order inside each file
+    File,
+    // Files in search order, lines by line number inside each file
+    Line,
+    // Alphabetically by the first matched text of each line
+    Match,
+}
+
+// When colors are used (--color=WHEN)
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ColorMode {
+    // Never print colors
+    Never,
+    // Print colors when stdout is a terminal and NO_COLOR is not set (same as -c)
+    Auto,
+    // Always print colors, even into files and pipes or with NO_COLOR set
+    Always,
+}
+
+// A color given by name on the command line, or as raw SGR codes from MYGREP_COLORS
+#[derive(Debug, Clone, PartialEq)]
+enum ColorSpec {
+    Named(Color),
+    // e.g. "01;31" for bold red
+    Sgr(String),
+}
+
+// How many colors RGB values can use, the terminal decides unless --color-256 or --color-truecolor is given
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ColorDepth {
+    Basic,
+    Palette256,
+    TrueColor,
+}
+
+// Colors used by -c for the different parts of a result
+// File names and separators stay uncolored unless MYGREP_COLORS sets them
+#[derive(Debug, Clone)]
+struct ColorPalette {
+    // None when --underline replaces the match color
+    match_color: Option<ColorSpec>,
+    line_number_color: ColorSpec,
+    file_name_color: Option<ColorSpec>,
+    separator_color: Option<ColorSpec>,
+}
+
+impl Default for ColorPalette {
+    fn default() -> ColorPalette {
+        ColorPalette {
+            match_color: Some(ColorSpec::Named(Color::Red)),
+            line_number_color: ColorSpec::Named(Color::Green),
+            file_name_color: None,
+            separator_color: None,
+        }
+    }
+}
+
 #[derive(Debug)]
 struct Config {
     pattern: String,
@@ -15,19 +180,394 @@ struct Config {
     recursive_directory: bool,
     print_filenames: bool,
     colored_output: bool,
+    files_without_match: bool,
+    binary_as_text: bool,
+    suppress_binary: bool,
+    count_matches: bool,
+    count_all_files: bool,
+    files_with_matches: bool,
+    null_terminated: bool,
+    search_engine: SearchEngine,
+    // None streams results as soon as they are found
+    sort_output: Option<SortBy>,
+    output_file: Option<PathBuf>,
+    append_output: bool,
+    exclude_from: Option<String>,
+    exclude_globs: Vec<glob::Pattern>,
+    include_from: Option<String>,
+    include_globs: Vec<glob::Pattern>,
+    follow_symlinks: bool,
+    dereference_paths: bool,
+    timeout: Option<Duration>,
+    rate_limit: Option<u64>,
+    tee_output: bool,
+    color_palette: ColorPalette,
+    hyperlinks: bool,
+    pretty: bool,
+    pager: Option<String>,
+    color_mode: ColorMode,
+    list_files: bool,
+    count_files: bool,
+    async_io: bool,
+    csv_output: bool,
+    xml_output: bool,
+    output_template: Option<minijinja::Environment<'static>>,
+    underline_match: bool,
+    column_width: Option<usize>,
+    junk_dirs: Vec<glob::Pattern>,
+    binary_extensions: Vec<String>,
+    skip_empty: bool,
+    max_line_length: Option<usize>,
+    truncate_long_lines: bool,
+    print_non_matching: bool,
+    fixed_strings: bool,
+    directory_mode: DirectoryMode,
+    input_limit: Option<u64>,
+    sample_size: Option<usize>,
+    skip_first: usize,
+    skip_last: usize,
+    line_regexp: bool,
+    exclude_patterns_file: Option<String>,
+    exclude_patterns: Option<aho_corasick::AhoCorasick>,
+    match_limit: Option<usize>,
+    highlight_line: bool,
+    separator: String,
+    // Between file name and line number with -f -n, the separator is used when not set
+    filename_separator: Option<String>,
+    max_columns: Option<usize>,
+    modified_after: Option<SystemTime>,
+    modified_before: Option<SystemTime>,
+    owner_uid: Option<u32>,
+    size_min: Option<u64>,
+    size_max: Option<u64>,
+    permission_filter: Option<PermissionFilter>,
+    file_kinds: Vec<FileKind>,
+    iname_globs: Vec<glob::Pattern>,
+    path_globs: Vec<glob::Pattern>,
+    exclude_dirs: Vec<glob::Pattern>,
+    suppress_errors: bool,
+    line_number_width: Option<usize>,
+    filename_width: Option<usize>,
+    escape_nul_bytes: bool,
+    color_reset_on_newline: bool,
+    count_bytes: bool,
+    streaming_binary_check: bool,
+    hex_output: bool,
+    // -U the regex is run over the whole file, so that matches can span lines
+    multiline: bool,
+    // The regex options for lines inside the searched text: . matches \n, ^ and $ match at every line
+    dot_all: bool,
+    multi_line: bool,
+    // -U files longer than this many bytes are searched line by line instead
+    multiline_limit: Option<usize>,
+    // --parallel search files on a thread per CPU core, see search_stream
+    parallel_search: bool,
+}
+
+impl Default for Config {
+    // The settings before any option is applied, parse_arguments starts from these
+    fn default() -> Config {
+        Config {
+            pattern: String::new(),
+            files: Vec::new(),
+            case_insensitive: false,
+            line_numbers: false,
+            invert_match: false,
+            recursive_directory: false,
+            print_filenames: false,
+            colored_output: false,
+            files_without_match: false,
+            binary_as_text: false,
+            suppress_binary: false,
+            count_matches: false,
+            count_all_files: false,
+            files_with_matches: false,
+            null_terminated: false,
+            search_engine: SearchEngine::Literal,
+            sort_output: None,
+            output_file: None,
+            append_output: false,
+            exclude_from: None,
+            exclude_globs: Vec::new(),
+            include_from: None,
+            include_globs: Vec::new(),
+            follow_symlinks: false,
+            dereference_paths: false,
+            timeout: None,
+            rate_limit: None,
+            tee_output: false,
+            color_palette: ColorPalette::default(),
+            hyperlinks: false,
+            pretty: false,
+            pager: None,
+            color_mode: ColorMode::Auto,
+            list_files: false,
+            count_files: false,
+            async_io: false,
+            csv_output: false,
+            xml_output: false,
+            output_template: None,
+            underline_match: false,
+            column_width: None,
+            junk_dirs: Vec::new(),
+            binary_extensions: Vec::new(),
+            skip_empty: false,
+            max_line_length: None,
+            truncate_long_lines: false,
+            print_non_matching: false,
+            fixed_strings: false,
+            directory_mode: DirectoryMode::Skip,
+            input_limit: None,
+            sample_size: None,
+            skip_first: 0,
+            skip_last: 0,
+            line_regexp: false,
+            exclude_patterns_file: None,
+            exclude_patterns: None,
+            match_limit: None,
+            highlight_line: false,
+            separator: ": ".to_string(),
+            filename_separator: None,
+            max_columns: None,
+            modified_after: None,
+            modified_before: None,
+            owner_uid: None,
+            size_min: None,
+            size_max: None,
+            permission_filter: None,
+            file_kinds: Vec::new(),
+            iname_globs: Vec::new(),
+            path_globs: Vec::new(),
+            exclude_dirs: Vec::new(),
+            suppress_errors: false,
+            line_number_width: None,
+            filename_width: None,
+            escape_nul_bytes: false,
+            color_reset_on_newline: true,
+            count_bytes: false,
+            streaming_binary_check: false,
+            hex_output: false,
+            multiline: false,
+            dot_all: false,
+            multi_line: false,
+            multiline_limit: None,
+            parallel_search: false,
+        }
+    }
 }
 
 fn print_help() {
     println!(
         "Usage: grep [OPTIONS] <pattern> <files...>
+       grep --files [OPTIONS] <files...>
 
 Options:
 -i                Case-insensitive search
+-x, --line-regexp, --whole-line
+                  Only select lines that match the pattern as a whole
 -n                Print line numbers
--v                Invert match (exclude lines that match the pattern)
+--line-number-width N
+                  Pad line numbers with spaces to N characters (longer numbers are not cut)
+--filename-width N
+                  Show file names in front of lines as exactly N characters, padded with spaces
+                  or cut from the left behind ... (e.g. ...src/main.rs)
+-v, --invert-match, --negate
+                  Invert match (exclude lines that match the pattern)
+--print-non-matching-lines
+                  Print the whole file: matching lines as usual, the other lines
+                  dimmed with -c and with a '-' separator after the prefix
+--ignore-empty-lines
+                  Never print lines that are empty or only contain whitespace
+--input-limit N   Read at most N bytes of each file, with a warning for files that are longer
+--skip-first N    Skip the first N lines of each file (e.g. a CSV header), -n still counts them
+--skip-last N     Skip the last N lines of each file
+--sample-size N   Only search every Nth line of each file, for a quick estimate on huge files
+--match-limit N   Highlight and report at most N matches per line
+--max-line-length N
+                  Skip lines longer than N bytes, with a warning for each skipped line
+--truncate-long-lines
+                  Search the first N bytes of lines longer than --max-line-length
+                  instead of skipping them
 -r                Recursive directory search
+--directory=MODE  What to do with directories given without -r: skip (default),
+                  read them like files (prints an error), or recurse into them
 -f                Print filenames
 -c                Enable colored output
+--color=WHEN      never, auto (same as -c) or always; auto also respects
+                  the NO_COLOR environment variable, always overrides it
+--color-match=COLOR
+                  Color of matched text with -c (default red)
+--underline       Underline matched text with -c instead of coloring it,
+                  together with --color-match the match is colored and underlined
+--highlight-line  With -c, give whole matching lines a yellow background, matches are bold
+--color-line-number=COLOR
+                  Color of line numbers with -c (default green)
+                  COLOR is a name (red, bright blue, ...), rgb(255,100,0) or #FF6400,
+                  RGB colors fall back to the nearest basic color unless COLORTERM=truecolor
+--color-truecolor Always print RGB colors as 24-bit colors, also without COLORTERM
+--color-256       Print RGB colors as the nearest of the 256 xterm colors
+                  Colors can also be set with MYGREP_COLORS, e.g. mt=01;31:fn=35:ln=32:se=36
+                  (mt match, fn file name, ln line number, se separator, values are SGR codes)
+--no-color-reset-on-newline
+                  Don't end colored lines with an extra reset code (by default every line with
+                  color codes ends with one, so that colors can't bleed into the next line)
+--color-reset-on-newline
+                  Turn the extra reset code back on
+--hyperlinks      With -c, make file names clickable (OSC 8 links) even if
+                  the terminal was not detected as supporting them
+                  (like colors, links only go into pipes and files with --color=always)
+--no-hyperlinks   Never print file names as links
+--pretty          Human-readable output for the terminal: file name headers,
+                  a line number column, highlighted matches and long lines
+                  wrapped to the terminal width (ignored if stdout is not a terminal)
+--separator-string STRING
+                  Text between the file name, line number and line (default \": \")
+--filename-separator STRING
+                  Text between the file name and the line number (default: the separator)
+--column-width N  Wrap output lines at N characters instead of the terminal width,
+                  continuation lines are indented under the text
+--hex-output      Print selected lines as hex dumps (offset, 16 bytes in hex, |text|) below the
+                  file name and line number, with -c the matched bytes are highlighted
+                  The dump shows the bytes as read, invalid UTF-8 and NUL bytes included
+--max-columns N   Cut lines after N characters (not counting the file name and line number)
+                  and end them with ...
+--csv             Print selected lines as CSV rows, one per match:
+                  file,line_number,column,match_start,match_end,text
+                  (-l and -L print a file column, --count file,count)
+--xml             Print results as an XML document:
+                  <grep><file name=...><match line=... col=...><text>...</text></match></file></grep>
+                  (-l and -L print <file name=.../>, --count <file name=... count=.../>)
+--template FILE   Print every match through a minijinja template, fields are available as
+                  result.file, result.line_number, result.column, result.match_start,
+                  result.match_end and result.text
+--pager COMMAND   Pipe the results through COMMAND, e.g. \"less -R\"
+--pager=auto      Use $MYGREP_PAGER or $PAGER when stdout is a terminal
+-L, --files-without-match
+                  Print only names of files with no selected lines
+                  (exit status 0 if any file was listed, 1 otherwise)
+-a, --text        Search binary files as if they were text
+--print-null-match
+                  Search files with NUL bytes as text and show each NUL as \\0 (implies -a),
+                  -F '\\0' matches them (as a regex the backslash has to be escaped: '\\\\0')
+-I, --suppress-binary-match
+                  Skip binary files without printing anything
+--skip-binary-content
+                  Also look for NUL bytes after the first 8 KB, one 8 KB block at a time: from the
+                  block with the first one on the file is treated as binary, the text lines of the
+                  blocks before it are still printed (with -I the rest of the file is skipped)
+--smart-binary    Also skip files with a known binary extension (.o, .so, .exe,
+                  .class, .jar, .wasm, .pdf, .png, .jpg, ...) without opening them
+--binary-ext-list FILE
+                  Skip files with an extension listed in FILE (one per line,
+                  # starts a comment), in addition to --smart-binary if given
+--count           Print the number of selected lines as file:count
+                  for each file that has at least one
+--count-per-file  Like --count, but also print file:0 for files with none
+-l, --files-with-matches
+                  Print only names of files with selected lines
+-s, --no-messages Do not print messages about missing or unreadable files (the exit status is
+                  still 2) or files that were only partly searched (cycles, --input-limit, ...)
+-Z, --null-print, --print0
+                  End file names printed by -l, -L and --files with a NUL byte
+                  instead of a newline (safe for xargs -0)
+--print-with-newline
+                  End file names with a newline again, overriding an earlier -Z
+--fuzzy DISTANCE  Match substrings that differ from the pattern by at most DISTANCE
+                  inserted, deleted or changed characters (slow for long patterns)
+--soundex         Match words that sound like the pattern (American Soundex, e.g.
+                  Robert matches Rupert), only meaningful for English words
+--similarity-metric=METRIC
+                  Match words similar to the pattern: levenshtein, jaro, jaro-winkler
+                  (default) or hamming (only words of the pattern's length)
+--similarity-threshold FLOAT
+                  Lowest similarity between 0.0 and 1.0 a word needs (default 0.9)
+--prefix-scale P  Jaro-Winkler weight of each common prefix character (default 0.1)
+--prefix-length N Jaro-Winkler counts a common prefix of at most N characters (default 4),
+                  P times N can be at most 1.0
+--invert-pattern FILE
+                  Leave out selected lines that contain any of the strings listed in FILE
+                  (one per line, # starts a comment; searched literally, not as regexes or globs)
+--word-list FILE  Search for every word listed in FILE (one per line, # starts a comment)
+                  as a whole word, e.g. to find banned terms; no pattern is given
+--base64, --base64-pattern
+                  Search for the pattern as it appears base64-encoded, anywhere inside base64 text
+                  (case-sensitive, the encoded forms are shown on stderr when it is a terminal)
+--url-encode, --url-encode-pattern
+                  Search for the pattern percent-encoded as in URLs, e.g. foo bar as foo%20bar
+--hex-pattern     The pattern is a sequence of bytes in hex, e.g. DEADBEEF for 0xDE 0xAD 0xBE 0xEF
+-F, --fixed-strings
+                  Search for the pattern literally, metacharacters like . and * match themselves
+-G, --basic-regexp
+                  Treat the pattern as a POSIX basic regular expression, as in grep:
+                  \\( \\) \\{{ \\}} \\| \\+ \\? are special, ( ) {{ }} | + ? match themselves
+-E, --extended-regexp
+                  Treat the pattern as an extended regular expression (regex crate syntax),
+                  the default for patterns with regex metacharacters
+-P, --perl-regexp, --pcre2
+                  Treat the pattern as a Perl-compatible regular expression
+                  (requires building with the pcre2 feature)
+--engine=MODE     Search backend: auto (default), literal (-F), extended (-E), basic (-G) or pcre2 (-P)
+                  (auto searches literally if the pattern has no regex metacharacters, otherwise
+                  with -E, or with pcre2 for look-around and back-references if it was built in)
+--char-class NAME=PATTERN
+                  Define a shorthand for a regex pattern, used as \\k{{NAME}} in the pattern,
+                  e.g. --char-class HEX=[0-9a-fA-F] -E '0x\\k{{HEX}}+' (definitions can use each other)
+-U, --multiline   Search the whole file at once so that matches can span lines, e.g. -E 'foo\\nbar'
+                  (^ and $ still match at line boundaries; needs -G, -E or -P)
+--multiline-dotall
+                  Also let . match newlines, e.g. -U -E 'begin.*end' for blocks over several lines
+--multiline-limit N
+                  Hold at most N bytes of a file in memory for -U, longer files are searched
+                  line by line (with a warning) and matches can't span lines there
+--capture NAME    Only match the text of the named capture group NAME
+                  (requires -G, -E or -P, e.g. -E --capture ip 'client=(?P<ip>[0-9.]+)')
+--sort-output-by=file|line|match
+                  Buffer all results and print them sorted by file name,
+                  line number or matched text
+--output-file PATH
+                  Write results to PATH instead of stdout (disables -c
+                  unless --color=always)
+--append-output   Append to the --output-file instead of truncating it
+--tee             Also write results to stdout when --output-file is set
+--exclude-from FILE
+                  Skip files matching any glob pattern listed in FILE
+                  (one pattern per line, # starts a comment)
+--include-from FILE
+                  Only search files matching a glob pattern listed in FILE
+--glob PATTERN    Recursively search the files matching PATTERN, e.g. \"**/*.rs\"
+                  (searches the current directory if no files are given)
+--iname PATTERN   Only search files whose name matches the glob PATTERN, ignoring case
+--path PATTERN    Only search files with a path component (a directory or the file name) matching the
+                  glob PATTERN, e.g. --path=tests for files anywhere below a directory named tests
+--newer-than FILE Only search files modified after FILE
+--older-than FILE Only search files modified before FILE
+--since DATE      Only search files modified after DATE, e.g. 2024-01-01 (local midnight)
+                  or 2024-01-01T00:00:00Z
+--user USER       Only search files owned by USER (Unix only, ignored elsewhere)
+--size-min BYTES  Only search files of at least BYTES (K, M and G suffixes are accepted, e.g. 10K)
+--size-max BYTES  Only search files of at most BYTES
+--permission MODE Only search files with these permissions (Unix only): exactly MODE in octal
+                  (0644), or a symbolic mode like a+x or u+w,o-w for bits that must be set/cleared
+--type TYPES      Only select these kinds of files, comma separated: f (regular files, the default),
+                  d (directories, only with --files), l (symlinks), b, c (block/char devices), p (FIFOs), s (sockets)
+--junk-dirs PATTERN
+                  Also skip directories matching PATTERN with -r (by default .git,
+                  __pycache__, node_modules, .svn and target are skipped)
+--no-junk-dirs    Descend into the default junk directories as well
+--exclude-dir PATTERN
+                  Do not descend into directories matching PATTERN with -r (kept with --no-junk-dirs)
+--files           Only print the files that would be searched, no pattern is given
+--count-files     Print the number of searched files at the end
+                  (on stderr when used with --files, --xml or --csv)
+--count-bytes     Print the number of bytes read from all files to stderr at the end
+--follow-symlinks Follow symbolic links during -r, warning about link cycles
+--dereference     Print file names as canonical paths with symlinks resolved
+--timeout SECONDS Abort with exit status 2 if the search takes longer than SECONDS
+--rate-limit N    Open at most N files per second
+--async           Read files concurrently with tokio, useful when I/O latency
+                  dominates (requires building with the tokio feature)
+--parallel        Search files on one thread per CPU core, results keep the order of the files
+                  (can't be used with --pretty or --rate-limit)
 -h, --help        Show help information"
     );
 }
@@ -37,22 +577,41 @@ fn parse_arguments() -> Result<Config, ()> {
     let mut arguments = env::args().skip(1);
 
     let mut user_config = Config {
-        pattern: String::new(),
-        files: Vec::new(),
-        case_insensitive: false,
-        line_numbers: false,
-        invert_match: false,
-        recursive_directory: false,
-        print_filenames: false,
-        colored_output: false,
+        color_palette: palette_from_env(),
+        ..Config::default()
     };
 
     let mut found_search_pattern = false;
+    let mut pattern_syntax = PatternSyntax::Literal;
+    // Without -F, -G, -E, -P or --engine the pattern is an extended regexp, searched literally if it can be
+    let mut auto_engine = true;
+    let mut capture_group = None;
+    let mut hyperlinks_choice = None;
+    let mut glob_search = false;
+    let mut template_file: Option<String> = None;
+    // Color options are only parsed after the loop, once the color depth is known
+    let mut match_color_name: Option<String> = None;
+    let mut line_number_color_name: Option<String> = None;
+    let mut color_depth = None;
+    let mut default_junk_dirs = true;
+    let mut binary_extension_file: Option<String> = None;
+    let mut fuzzy_distance: Option<usize> = None;
+    let mut soundex_search = false;
+    let mut similarity_metric: Option<SimilarityMetric> = None;
+    let mut similarity_threshold: Option<f64> = None;
+    let mut jaro_prefix_scale: Option<f64> = None;
+    let mut jaro_prefix_length: Option<usize> = None;
+    let mut word_list_file: Option<String> = None;
+    let mut hex_pattern = false;
+    let mut base64_pattern = false;
+    let mut url_encode_pattern = false;
+    let mut char_classes: Vec<(String, String)> = Vec::new();
 
     while let Some(current_argument) = arguments.next() {
         if current_argument == "-h" || current_argument == "--help" {
+            // Asking for help is not an error
             print_help();
-            return Err(());
+            process::exit(0);
         }
 
         if current_argument == "-i" {
@@ -63,7 +622,7 @@ fn parse_arguments() -> Result<Config, ()> {
             user_config.line_numbers = true;
             continue;
         }
-        if current_argument == "-v" {
+        if current_argument == "-v" || current_argument == "--invert-match" || current_argument == "--negate" {
             user_config.invert_match = true;
             continue;
         }
@@ -79,219 +638,4187 @@ fn parse_arguments() -> Result<Config, ()> {
             user_config.colored_output = true;
             continue;
         }
-
-        if !found_search_pattern {
-            user_config.pattern = current_argument;
-            found_search_pattern = true;
-        } else {
-            user_config.files.push(current_argument);
+        if current_argument == "-L" || current_argument == "--files-without-match" {
+            user_config.files_without_match = true;
+            continue;
         }
-    }
-
-    if !found_search_pattern {
-        print_help();
-        return Err(());
-    }
-
-    if user_config.files.is_empty() {
-        print_help();
-        return Err(());
-    }
-
-    Ok(user_config)
-}
-
-// Collect the list of files
-// Process the file and directory paths entered by the user
-fn collect_files(input_paths: &[String], is_recursive_search: bool) -> Vec<PathBuf> {
-    let mut file_list = Vec::new();
-
-    for user_input_path in input_paths {
-        let path = Path::new(user_input_path);
-        if path.is_file() {
-            if !is_junk_file(path) {
-                file_list.push(path.to_path_buf());
+        if current_argument == "-a" || current_argument == "--text" {
+            user_config.binary_as_text = true;
+            continue;
+        }
+        if current_argument == "--print-null-match" {
+            user_config.escape_nul_bytes = true;
+            // Files with NUL bytes would otherwise only be reported as binary
+            user_config.binary_as_text = true;
+            continue;
+        }
+        if current_argument == "--skip-binary-content" {
+            user_config.streaming_binary_check = true;
+            continue;
+        }
+        if current_argument == "-I" || current_argument == "--suppress-binary-match" {
+            user_config.suppress_binary = true;
+            continue;
+        }
+        if current_argument == "--count" {
+            user_config.count_matches = true;
+            continue;
+        }
+        if current_argument == "--count-per-file" {
+            user_config.count_matches = true;
+            user_config.count_all_files = true;
+            continue;
+        }
+        if current_argument == "-l" || current_argument == "--files-with-matches" {
+            user_config.files_with_matches = true;
+            continue;
+        }
+        if current_argument == "-s" || current_argument == "--no-messages" {
+            user_config.suppress_errors = true;
+            continue;
+        }
+        if current_argument == "--color-reset-on-newline" {
+            user_config.color_reset_on_newline = true;
+            continue;
+        }
+        if current_argument == "--no-color-reset-on-newline" {
+            user_config.color_reset_on_newline = false;
+            continue;
+        }
+        if current_argument == "-Z" || current_argument == "--null-print" || current_argument == "--print0" {
+            user_config.null_terminated = true;
+            continue;
+        }
+        if current_argument == "--print-with-newline" {
+            // Undoes an earlier -Z, the last one given wins
+            user_config.null_terminated = false;
+            continue;
+        }
+        if current_argument == "-x" || current_argument == "--line-regexp" || current_argument == "--whole-line" {
+            user_config.line_regexp = true;
+            continue;
+        }
+        if current_argument == "--fuzzy" {
+            match arguments.next().and_then(|distance_text| distance_text.parse::<usize>().ok()) {
+                Some(max_distance) => fuzzy_distance = Some(max_distance),
+                None => {
+                    eprintln!("grep: --fuzzy requires a distance");
+                    return Err(());
+                }
             }
+            continue;
         }
-        else if path.is_dir() {
-            if is_recursive_search {
-                for directory_entry in WalkDir::new(path).into_iter().filter_map(Result::ok) {
-                    let file_path = directory_entry.path();
-                    if file_path.is_file() && !is_junk_file(file_path) {
-                        file_list.push(file_path.to_path_buf());
-                    }
+        if current_argument == "--soundex" {
+            soundex_search = true;
+            continue;
+        }
+        if let Some(metric_name) = current_argument.strip_prefix("--similarity-metric=") {
+            similarity_metric = Some(match metric_name {
+                "levenshtein" => SimilarityMetric::Levenshtein,
+                "jaro" => SimilarityMetric::Jaro,
+                "jaro-winkler" => {
+                    SimilarityMetric::JaroWinkler { prefix_scale: DEFAULT_PREFIX_SCALE, max_prefix_length: DEFAULT_MAX_PREFIX_LENGTH }
+                }
+                "hamming" => SimilarityMetric::Hamming,
+                _ => {
+                    eprintln!("grep: unknown similarity metric '{}' (expected levenshtein, jaro, jaro-winkler or hamming)", metric_name);
+                    return Err(());
+                }
+            });
+            continue;
+        }
+        if current_argument == "--prefix-scale" {
+            match arguments.next().and_then(|scale_text| scale_text.parse::<f64>().ok()) {
+                // Checked against the prefix length once all arguments are known
+                Some(prefix_scale) if (0.0..=1.0).contains(&prefix_scale) => jaro_prefix_scale = Some(prefix_scale),
+                _ => {
+                    eprintln!("grep: --prefix-scale requires a number between 0.0 and 1.0");
+                    return Err(());
                 }
-            } else {
-
             }
-        } else {
-            if path.exists() && path.is_file() && !is_junk_file(path) {
-                file_list.push(path.to_path_buf());
+            continue;
+        }
+        if current_argument == "--prefix-length" {
+            match arguments.next().and_then(|length_text| length_text.parse::<usize>().ok()) {
+                Some(prefix_length) if prefix_length > 0 => jaro_prefix_length = Some(prefix_length),
+                _ => {
+                    eprintln!("grep: --prefix-length requires a positive number of characters");
+                    return Err(());
+                }
             }
+            continue;
         }
-    }
-    // file_list.sort_by(|a, b| b.cmp(a));
-    file_list
-}
-
-
-// Filter some common "junk files"
-fn is_junk_file(file_path: &Path) -> bool {
-    // Get the file name. If it is successfully obtained and can be converted into a string, check whether it is a junk file.
-    if let Some(file_name) = file_path.file_name() {
-        if let Some(file_name_str) = file_name.to_str() {
-            if file_name_str.starts_with("._") {
-                return true;
+        if current_argument == "--similarity-threshold" {
+            match arguments.next().and_then(|threshold_text| threshold_text.parse::<f64>().ok()) {
+                Some(threshold) if (0.0..=1.0).contains(&threshold) => similarity_threshold = Some(threshold),
+                _ => {
+                    eprintln!("grep: --similarity-threshold requires a number between 0.0 and 1.0");
+                    return Err(());
+                }
             }
-            if file_name_str == ".DS_Store" {
-                return true;
+            continue;
+        }
+        if current_argument == "--invert-pattern" {
+            match arguments.next() {
+                Some(pattern_file) => user_config.exclude_patterns_file = Some(pattern_file),
+                None => {
+                    eprintln!("grep: --invert-pattern requires a file");
+                    return Err(());
+                }
             }
+            continue;
         }
-    }
-    false
-}
-
-// Search for matching lines in a single file and print the results
-fn search_file(file_path: &Path, config: &Config) -> io::Result<()> {
-    let file = File::open(file_path)?;
-    let reader = BufReader::new(file);
-    let mut current_line_number: usize = 0;
-
-    for line_result in reader.lines() {
-        current_line_number += 1;
-
-        let line_content = line_result?;
-
-        let matches_found = find_matches_in_line(&line_content, &config.pattern, config.case_insensitive);
-
-        let should_print_line = if config.invert_match {
-            // Print this line only if no match is found
-            matches_found.is_empty()
-        } else {
-            // If a match is found, print the line
-            !matches_found.is_empty()
-        };
-
-        if should_print_line {
-            // taking color output options into account
-            let text_to_print = if config.colored_output && !matches_found.is_empty() {
-                // -c Add red highlight to matching text
-                colorize_hits(&line_content, &matches_found)
-            } else {
-                line_content.clone()
+        if current_argument == "--word-list" {
+            match arguments.next() {
+                Some(word_file) => word_list_file = Some(word_file),
+                None => {
+                    eprintln!("grep: --word-list requires a file");
+                    return Err(());
+                }
+            }
+            continue;
+        }
+        if current_argument == "-F" || current_argument == "--fixed-strings" {
+            user_config.fixed_strings = true;
+            continue;
+        }
+        if current_argument == "-G" || current_argument == "--basic-regexp" {
+            pattern_syntax = PatternSyntax::Basic;
+            auto_engine = false;
+            user_config.fixed_strings = false;
+            continue;
+        }
+        if current_argument == "-E" || current_argument == "--extended-regexp" {
+            pattern_syntax = PatternSyntax::Extended;
+            auto_engine = false;
+            user_config.fixed_strings = false;
+            continue;
+        }
+        if current_argument == "-P" || current_argument == "--perl-regexp" || current_argument == "--pcre2" {
+            pattern_syntax = PatternSyntax::Perl;
+            auto_engine = false;
+            user_config.fixed_strings = false;
+            continue;
+        }
+        if current_argument == "-U" || current_argument == "--multiline" {
+            user_config.multiline = true;
+            user_config.multi_line = true;
+            continue;
+        }
+        if current_argument == "--multiline-dotall" {
+            user_config.dot_all = true;
+            continue;
+        }
+        if current_argument == "--multiline-limit" {
+            match arguments.next().and_then(|limit_text| limit_text.parse::<usize>().ok()) {
+                Some(multiline_limit) => user_config.multiline_limit = Some(multiline_limit),
+                None => {
+                    eprintln!("grep: --multiline-limit requires a number of bytes");
+                    return Err(());
+                }
+            }
+            continue;
+        }
+        if let Some(engine_name) = current_argument.strip_prefix("--engine=") {
+            // The last engine option wins, also over an earlier -F
+            user_config.fixed_strings = false;
+            match engine_name {
+                "literal" => {
+                    pattern_syntax = PatternSyntax::Literal;
+                    auto_engine = false;
+                }
+                "extended" => {
+                    pattern_syntax = PatternSyntax::Extended;
+                    auto_engine = false;
+                }
+                "basic" => {
+                    pattern_syntax = PatternSyntax::Basic;
+                    auto_engine = false;
+                }
+                "pcre2" => {
+                    pattern_syntax = PatternSyntax::Perl;
+                    auto_engine = false;
+                }
+                "auto" => auto_engine = true,
+                _ => {
+                    eprintln!("grep: unknown engine '{}' (expected auto, literal, extended, basic or pcre2)", engine_name);
+                    return Err(());
+                }
+            }
+            continue;
+        }
+        if let Some(sort_key) = current_argument.strip_prefix("--sort-output-by=") {
+            user_config.sort_output = match sort_key {
+                "file" => Some(SortBy::File),
+                "line" => Some(SortBy::Line),
+                "match" => Some(SortBy::Match),
+                _ => {
+                    eprintln!("grep: unknown sort key '{}' (expected file, line or match)", sort_key);
+                    return Err(());
+                }
             };
-
-            if config.print_filenames && config.line_numbers {
-                // -f + -n Display file name and line number
-                println!("{}: {}: {}", file_path.display(), current_line_number, text_to_print);
-            } else if config.print_filenames {
-                // -f file name
-                println!("{}: {}", file_path.display(), text_to_print);
-            } else if config.line_numbers {
-                // -n line number
-                println!("{}: {}", current_line_number, text_to_print);
-            } else {
-                // print text content
-                println!("{}", text_to_print);
+            continue;
+        }
+        if current_argument == "--output-file" {
+            match arguments.next() {
+                Some(output_path) => user_config.output_file = Some(PathBuf::from(output_path)),
+                None => {
+                    eprintln!("grep: --output-file requires a path");
+                    return Err(());
+                }
             }
+            continue;
         }
-    }
-    Ok(())
-}
-
-// Find all matches of a pattern in a line of text
-// Returns a vector of (start, end) byte positions for each match found
-fn find_matches_in_line(line_text: &str, search_pattern: &str, ignore_case: bool) -> Vec<(usize, usize)> {
-    let mut match_positions = Vec::new();
-    if search_pattern.is_empty() {
-        return match_positions;
-    }
-
-    // convert to lowercase if ignore_case is true
-    let (search_text, pattern_to_find) = if ignore_case {
-        (line_text.to_ascii_lowercase(), search_pattern.to_ascii_lowercase())
-    } else {
-        (line_text.to_string(), search_pattern.to_string())
-    };
-
-    let text_bytes = search_text.as_bytes();
-    let pattern_bytes = pattern_to_find.as_bytes();
-
-    // pattern cannot be empty or longer than text
-    if pattern_bytes.is_empty() || pattern_bytes.len() > text_bytes.len() {
-        return match_positions;
-    }
-
-    // Search through the text from left to right
-    let mut current_position = 0;
-    let pattern_length = pattern_bytes.len();
-
-    while current_position + pattern_length <= text_bytes.len() {
-        // Compare current slice of text with the pattern
-        let current_slice = &text_bytes[current_position..current_position + pattern_length];
-
-        if current_slice == pattern_bytes {
-            // Find a match! Record the start and end positions
-            let match_start = current_position;
-            let match_end = current_position + pattern_length;
-            match_positions.push((match_start, match_end));
-            current_position += pattern_length;
-        } else {
-            // No match found, move to next position
-            current_position += 1;
+        if current_argument == "--append-output" {
+            user_config.append_output = true;
+            continue;
         }
-    }
-
-    match_positions
-}
-
-// Add red color to matched text segments
-fn colorize_hits(original_line: &str, match_ranges: &[(usize, usize)]) -> String {
-    if match_ranges.is_empty() {
-        return original_line.to_string();
-    }
-
-    // Create a new string to build the colored result
-    let mut colored_result = String::with_capacity(original_line.len() + match_ranges.len() * 10);
-    let mut last_processed_position = 0;
-
-    for &(match_start, match_end) in match_ranges {
-        // Add the normal text before the current match
-        if match_start > last_processed_position {
-            let normal_text_before_match = &original_line[last_processed_position..match_start];
-            colored_result.push_str(normal_text_before_match);
+        if current_argument == "--exclude-from" {
+            match arguments.next() {
+                Some(exclude_file) => user_config.exclude_from = Some(exclude_file),
+                None => {
+                    eprintln!("grep: --exclude-from requires a file");
+                    return Err(());
+                }
+            }
+            continue;
         }
-
-        // Add red color
-        let matched_text_segment = &original_line[match_start..match_end];
-        let red_colored_text = matched_text_segment.red().to_string();
-        colored_result.push_str(&red_colored_text);
-        last_processed_position = match_end;
-    }
-
-    // Add remaining text after the last match
-    if last_processed_position < original_line.len() {
-        let remaining_text = &original_line[last_processed_position..];
-        colored_result.push_str(remaining_text);
-    }
-    colored_result
-}
-
-
-fn main() {
-    let config = match parse_arguments() {
-        Ok(config) => config,
-        Err(_) => {
-            return;
+        if current_argument == "--include-from" {
+            match arguments.next() {
+                Some(include_file) => user_config.include_from = Some(include_file),
+                None => {
+                    eprintln!("grep: --include-from requires a file");
+                    return Err(());
+                }
+            }
+            continue;
         }
-    };
-
-    // Convert user-provided paths into actual file list to search
-    let files_to_search = collect_files(&config.files, config.recursive_directory);
+        if current_argument == "--follow-symlinks" {
+            user_config.follow_symlinks = true;
+            continue;
+        }
+        if current_argument == "--dereference" {
+            user_config.dereference_paths = true;
+            continue;
+        }
+        if current_argument == "--timeout" {
+            let timeout_seconds = arguments.next().and_then(|seconds_text| seconds_text.parse::<f64>().ok());
+            match timeout_seconds {
+                Some(seconds) if seconds.is_finite() && seconds > 0.0 => {
+                    user_config.timeout = Some(Duration::from_secs_f64(seconds));
+                }
+                _ => {
+                    eprintln!("grep: --timeout requires a positive number of seconds");
+                    return Err(());
+                }
+            }
+            continue;
+        }
+        if current_argument == "--rate-limit" {
+            match arguments.next().and_then(|limit_text| limit_text.parse::<u64>().ok()) {
+                Some(files_per_second) if files_per_second > 0 => user_config.rate_limit = Some(files_per_second),
+                _ => {
+                    eprintln!("grep: --rate-limit requires a positive number of files per second");
+                    return Err(());
+                }
+            }
+            continue;
+        }
+        if current_argument == "--tee" {
+            user_config.tee_output = true;
+            continue;
+        }
+        if let Some(color_when) = current_argument.strip_prefix("--color=") {
+            user_config.color_mode = match color_when {
+                "never" => ColorMode::Never,
+                "auto" => ColorMode::Auto,
+                "always" => ColorMode::Always,
+                _ => {
+                    eprintln!("grep: unknown color mode '{}' (expected never, auto or always)", color_when);
+                    return Err(());
+                }
+            };
+            user_config.colored_output = user_config.color_mode != ColorMode::Never;
+            continue;
+        }
+        if let Some(color_name) = current_argument.strip_prefix("--color-match=") {
+            match_color_name = Some(color_name.to_string());
+            continue;
+        }
+        if current_argument == "--highlight-line" {
+            user_config.highlight_line = true;
+            continue;
+        }
+        if current_argument == "--underline" {
+            user_config.underline_match = true;
+            continue;
+        }
+        if let Some(color_name) = current_argument.strip_prefix("--color-line-number=") {
+            line_number_color_name = Some(color_name.to_string());
+            continue;
+        }
+        if current_argument == "--color-truecolor" {
+            color_depth = Some(ColorDepth::TrueColor);
+            continue;
+        }
+        if current_argument == "--color-256" {
+            color_depth = Some(ColorDepth::Palette256);
+            continue;
+        }
+        if current_argument == "--hyperlinks" {
+            hyperlinks_choice = Some(true);
+            continue;
+        }
+        if current_argument == "--no-hyperlinks" {
+            hyperlinks_choice = Some(false);
+            continue;
+        }
+        if current_argument == "--pretty" {
+            user_config.pretty = true;
+            continue;
+        }
+        if current_argument == "--pager" || current_argument.starts_with("--pager=") {
+            let pager_command = match current_argument.strip_prefix("--pager=") {
+                Some(pager_command) => Some(pager_command.to_string()),
+                None => arguments.next(),
+            };
+            match pager_command {
+                Some(pager_command) if pager_command == "auto" => user_config.pager = default_pager(),
+                Some(pager_command) if !pager_command.trim().is_empty() => user_config.pager = Some(pager_command),
+                _ => {
+                    eprintln!("grep: --pager requires a command");
+                    return Err(());
+                }
+            }
+            continue;
+        }
+        if current_argument == "--glob" {
+            let glob_text = match arguments.next() {
+                Some(glob_text) => glob_text,
+                None => {
+                    eprintln!("grep: --glob requires a pattern");
+                    return Err(());
+                }
+            };
+            match glob::Pattern::new(&glob_text) {
+                Ok(glob_pattern) => user_config.include_globs.push(glob_pattern),
+                Err(glob_error) => {
+                    eprintln!("grep: invalid glob '{}': {}", glob_text, glob_error);
+                    return Err(());
+                }
+            }
+            // Same as -r --include=PATTERN
+            user_config.recursive_directory = true;
+            glob_search = true;
+            continue;
+        }
+        if current_argument == "--iname" {
+            let Some(glob_text) = arguments.next() else {
+                eprintln!("grep: --iname requires a pattern");
+                return Err(());
+            };
+            // File names are lowercased before matching, so the pattern is as well
+            match glob::Pattern::new(&glob_text.to_lowercase()) {
+                Ok(glob_pattern) => user_config.iname_globs.push(glob_pattern),
+                Err(glob_error) => {
+                    eprintln!("grep: invalid glob '{}': {}", glob_text, glob_error);
+                    return Err(());
+                }
+            }
+            continue;
+        }
+        if current_argument == "--path" || current_argument.starts_with("--path=") {
+            let glob_text = match current_argument.strip_prefix("--path=") {
+                Some(glob_text) => glob_text.to_string(),
+                None => match arguments.next() {
+                    Some(glob_text) => glob_text,
+                    None => {
+                        eprintln!("grep: --path requires a pattern");
+                        return Err(());
+                    }
+                },
+            };
+            match glob::Pattern::new(&glob_text) {
+                Ok(glob_pattern) => user_config.path_globs.push(glob_pattern),
+                Err(glob_error) => {
+                    eprintln!("grep: invalid glob '{}': {}", glob_text, glob_error);
+                    return Err(());
+                }
+            }
+            continue;
+        }
+        if current_argument == "--files" {
+            user_config.list_files = true;
+            continue;
+        }
+        if current_argument == "--count-bytes" {
+            user_config.count_bytes = true;
+            continue;
+        }
+        if current_argument == "--count-files" {
+            user_config.count_files = true;
+            continue;
+        }
+        if current_argument == "--async" {
+            if cfg!(not(feature = "tokio")) {
+                eprintln!("grep: --async is not available, rebuild with `cargo build --features tokio`");
+                return Err(());
+            }
+            user_config.async_io = true;
+            continue;
+        }
+        if current_argument == "--parallel" {
+            user_config.parallel_search = true;
+            continue;
+        }
+        if current_argument == "--csv" {
+            user_config.csv_output = true;
+            continue;
+        }
+        if current_argument == "--xml" {
+            user_config.xml_output = true;
+            continue;
+        }
+        if current_argument == "--template" {
+            match arguments.next() {
+                Some(template_path) => template_file = Some(template_path),
+                None => {
+                    eprintln!("grep: --template requires a file");
+                    return Err(());
+                }
+            }
+            continue;
+        }
+        if current_argument == "--separator-string" {
+            match arguments.next() {
+                Some(separator) => user_config.separator = separator,
+                None => {
+                    eprintln!("grep: --separator-string requires a string");
+                    return Err(());
+                }
+            }
+            continue;
+        }
+        if current_argument == "--filename-separator" {
+            match arguments.next() {
+                Some(filename_separator) => user_config.filename_separator = Some(filename_separator),
+                None => {
+                    eprintln!("grep: --filename-separator requires a string");
+                    return Err(());
+                }
+            }
+            continue;
+        }
+        if current_argument == "--line-number-width" {
+            match arguments.next().and_then(|width_text| width_text.parse::<usize>().ok()) {
+                Some(line_number_width) => user_config.line_number_width = Some(line_number_width),
+                None => {
+                    eprintln!("grep: --line-number-width requires a number of characters");
+                    return Err(());
+                }
+            }
+            continue;
+        }
+        if current_argument == "--filename-width" {
+            match arguments.next().and_then(|width_text| width_text.parse::<usize>().ok()) {
+                Some(filename_width) if filename_width > 0 => user_config.filename_width = Some(filename_width),
+                _ => {
+                    eprintln!("grep: --filename-width requires a positive number of characters");
+                    return Err(());
+                }
+            }
+            continue;
+        }
+        if current_argument == "--hex-output" {
+            user_config.hex_output = true;
+            continue;
+        }
+        if current_argument == "--max-columns" {
+            match arguments.next().and_then(|columns_text| columns_text.parse::<usize>().ok()) {
+                Some(max_columns) => user_config.max_columns = Some(max_columns),
+                None => {
+                    eprintln!("grep: --max-columns requires a number of characters");
+                    return Err(());
+                }
+            }
+            continue;
+        }
+        if current_argument == "--column-width" {
+            match arguments.next().and_then(|width_text| width_text.parse::<usize>().ok()) {
+                Some(column_width) if column_width > 0 => user_config.column_width = Some(column_width),
+                _ => {
+                    eprintln!("grep: --column-width requires a positive number of characters");
+                    return Err(());
+                }
+            }
+            continue;
+        }
+        if current_argument == "--newer-than" || current_argument == "--older-than" {
+            let Some(reference_file) = arguments.next() else {
+                eprintln!("grep: {} requires a file", current_argument);
+                return Err(());
+            };
+            let reference_time = modification_time(&reference_file)?;
+            if current_argument == "--newer-than" {
+                user_config.modified_after = Some(reference_time);
+            } else {
+                user_config.modified_before = Some(reference_time);
+            }
+            continue;
+        }
+        if current_argument == "--since" {
+            let Some(date_text) = arguments.next() else {
+                eprintln!("grep: --since requires a date");
+                return Err(());
+            };
+            user_config.modified_after = Some(parse_since_date(&date_text)?);
+            continue;
+        }
+        if current_argument == "--user" {
+            let Some(user_name) = arguments.next() else {
+                eprintln!("grep: --user requires a user name");
+                return Err(());
+            };
+            user_config.owner_uid = resolve_user_id(&user_name)?;
+            continue;
+        }
+        if current_argument == "--size-min" || current_argument == "--size-max" {
+            let Some(file_size) = arguments.next().as_deref().and_then(parse_file_size) else {
+                eprintln!("grep: {} requires a size like 512, 10K, 2M or 3G", current_argument);
+                return Err(());
+            };
+            if current_argument == "--size-min" {
+                user_config.size_min = Some(file_size);
+            } else {
+                user_config.size_max = Some(file_size);
+            }
+            continue;
+        }
+        if current_argument == "--permission" {
+            let Some(permission_text) = arguments.next() else {
+                eprintln!("grep: --permission requires a mode");
+                return Err(());
+            };
+            let Some(permission_filter) = parse_permission_filter(&permission_text) else {
+                eprintln!("grep: invalid mode '{}' (expected e.g. 0644, a+x or u+w,o-w)", permission_text);
+                return Err(());
+            };
+            if cfg!(not(unix)) {
+                eprintln!("grep: warning: --permission is only supported on Unix, ignoring it");
+                continue;
+            }
+            user_config.permission_filter = Some(permission_filter);
+            continue;
+        }
+        if current_argument == "--type" {
+            let Some(type_list) = arguments.next() else {
+                eprintln!("grep: --type requires a list of file types");
+                return Err(());
+            };
+            for type_name in type_list.split(',') {
+                let file_kind = match type_name {
+                    "f" => FileKind::Regular,
+                    "d" => FileKind::Directory,
+                    "l" => FileKind::Symlink,
+                    "b" => FileKind::BlockDevice,
+                    "c" => FileKind::CharDevice,
+                    "p" => FileKind::Fifo,
+                    "s" => FileKind::Socket,
+                    _ => {
+                        eprintln!("grep: invalid file type '{}' (expected f, d, l, b, c, p or s)", type_name);
+                        return Err(());
+                    }
+                };
+                if !user_config.file_kinds.contains(&file_kind) {
+                    user_config.file_kinds.push(file_kind);
+                }
+            }
+            continue;
+        }
+        if current_argument == "--junk-dirs" {
+            let glob_text = match arguments.next() {
+                Some(glob_text) => glob_text,
+                None => {
+                    eprintln!("grep: --junk-dirs requires a pattern");
+                    return Err(());
+                }
+            };
+            match glob::Pattern::new(&glob_text) {
+                Ok(glob_pattern) => user_config.junk_dirs.push(glob_pattern),
+                Err(glob_error) => {
+                    eprintln!("grep: invalid glob '{}': {}", glob_text, glob_error);
+                    return Err(());
+                }
+            }
+            continue;
+        }
+        if current_argument == "--exclude-dir" || current_argument.starts_with("--exclude-dir=") {
+            let glob_text = match current_argument.strip_prefix("--exclude-dir=") {
+                Some(glob_text) => glob_text.to_string(),
+                None => match arguments.next() {
+                    Some(glob_text) => glob_text,
+                    None => {
+                        eprintln!("grep: --exclude-dir requires a pattern");
+                        return Err(());
+                    }
+                },
+            };
+            match glob::Pattern::new(&glob_text) {
+                Ok(glob_pattern) => user_config.exclude_dirs.push(glob_pattern),
+                Err(glob_error) => {
+                    eprintln!("grep: invalid glob '{}': {}", glob_text, glob_error);
+                    return Err(());
+                }
+            }
+            continue;
+        }
+        if current_argument == "--no-junk-dirs" {
+            default_junk_dirs = false;
+            continue;
+        }
+        if current_argument == "--smart-binary" {
+            user_config.binary_extensions.extend(DEFAULT_BINARY_EXTENSIONS.iter().map(|extension| extension.to_string()));
+            continue;
+        }
+        if current_argument == "--binary-ext-list" {
+            match arguments.next() {
+                Some(extension_file) => binary_extension_file = Some(extension_file),
+                None => {
+                    eprintln!("grep: --binary-ext-list requires a file");
+                    return Err(());
+                }
+            }
+            continue;
+        }
+        if current_argument == "--ignore-empty-lines" {
+            user_config.skip_empty = true;
+            continue;
+        }
+        if current_argument == "--match-limit" {
+            match arguments.next().and_then(|limit_text| limit_text.parse::<usize>().ok()) {
+                Some(match_limit) if match_limit > 0 => user_config.match_limit = Some(match_limit),
+                _ => {
+                    eprintln!("grep: --match-limit requires a positive number of matches");
+                    return Err(());
+                }
+            }
+            continue;
+        }
+        if current_argument == "--max-line-length" {
+            match arguments.next().and_then(|length_text| length_text.parse::<usize>().ok()) {
+                Some(max_line_length) => user_config.max_line_length = Some(max_line_length),
+                None => {
+                    eprintln!("grep: --max-line-length requires a number of bytes");
+                    return Err(());
+                }
+            }
+            continue;
+        }
+        if current_argument == "--truncate-long-lines" {
+            user_config.truncate_long_lines = true;
+            continue;
+        }
+        if current_argument == "--print-non-matching-lines" {
+            user_config.print_non_matching = true;
+            continue;
+        }
+        if let Some(directory_mode) = current_argument.strip_prefix("--directory=") {
+            user_config.directory_mode = match directory_mode {
+                "read" => DirectoryMode::Read,
+                "skip" => DirectoryMode::Skip,
+                "recurse" => DirectoryMode::Recurse,
+                _ => {
+                    eprintln!("grep: unknown directory mode '{}' (expected read, skip or recurse)", directory_mode);
+                    return Err(());
+                }
+            };
+            continue;
+        }
+        if current_argument == "--input-limit" {
+            match arguments.next().and_then(|limit_text| limit_text.parse::<u64>().ok()) {
+                Some(input_limit) => user_config.input_limit = Some(input_limit),
+                None => {
+                    eprintln!("grep: --input-limit requires a number of bytes");
+                    return Err(());
+                }
+            }
+            continue;
+        }
+        if current_argument == "--skip-first" {
+            match arguments.next().and_then(|skip_text| skip_text.parse::<usize>().ok()) {
+                Some(skip_first) => user_config.skip_first = skip_first,
+                None => {
+                    eprintln!("grep: --skip-first requires a number of lines");
+                    return Err(());
+                }
+            }
+            continue;
+        }
+        if current_argument == "--skip-last" {
+            match arguments.next().and_then(|skip_text| skip_text.parse::<usize>().ok()) {
+                Some(skip_last) => user_config.skip_last = skip_last,
+                None => {
+                    eprintln!("grep: --skip-last requires a number of lines");
+                    return Err(());
+                }
+            }
+            continue;
+        }
+        if current_argument == "--sample-size" {
+            match arguments.next().and_then(|sample_text| sample_text.parse::<usize>().ok()) {
+                Some(sample_size) if sample_size > 0 => user_config.sample_size = Some(sample_size),
+                _ => {
+                    eprintln!("grep: --sample-size requires a positive number of lines");
+                    return Err(());
+                }
+            }
+            continue;
+        }
+        if current_argument == "--char-class" {
+            let Some(class_definition) = arguments.next() else {
+                eprintln!("grep: --char-class requires NAME=PATTERN");
+                return Err(());
+            };
+            let Some((class_name, class_pattern)) = class_definition.split_once('=').filter(|(class_name, _)| {
+                !class_name.is_empty() && class_name.chars().all(|name_char| name_char.is_ascii_alphanumeric() || name_char == '_')
+            }) else {
+                eprintln!("grep: invalid --char-class '{}' (expected NAME=PATTERN, e.g. HEX=[0-9a-fA-F])", class_definition);
+                return Err(());
+            };
+            // A later definition of the same name replaces the earlier one
+            char_classes.retain(|(defined_name, _)| defined_name != class_name);
+            char_classes.push((class_name.to_string(), class_pattern.to_string()));
+            continue;
+        }
+        if current_argument == "--base64" || current_argument == "--base64-pattern" {
+            base64_pattern = true;
+            continue;
+        }
+        if current_argument == "--url-encode" || current_argument == "--url-encode-pattern" {
+            url_encode_pattern = true;
+            continue;
+        }
+        if current_argument == "--hex-pattern" {
+            hex_pattern = true;
+            continue;
+        }
+        if current_argument == "--capture" {
+            match arguments.next() {
+                Some(group_name) => capture_group = Some(group_name),
+                None => {
+                    eprintln!("grep: --capture requires a group name");
+                    return Err(());
+                }
+            }
+            continue;
+        }
+
+        if !found_search_pattern {
+            user_config.pattern = current_argument;
+            found_search_pattern = true;
+        } else {
+            user_config.files.push(current_argument);
+        }
+    }
+
+    // --files and --word-list take no pattern, so the first positional argument is a file as well
+    if (user_config.list_files || word_list_file.is_some()) && found_search_pattern {
+        let first_file = std::mem::take(&mut user_config.pattern);
+        user_config.files.insert(0, first_file);
+    }
+
+    if !found_search_pattern && !user_config.list_files && word_list_file.is_none() {
+        print_help();
+        return Err(());
+    }
+
+    // --glob searches below the current directory unless told otherwise
+    if glob_search && user_config.files.is_empty() {
+        user_config.files.push(".".to_string());
+    }
+
+    if user_config.files.is_empty() {
+        print_help();
+        return Err(());
+    }
+
+    // Reordering would break the nesting of the XML document
+    if user_config.xml_output && user_config.sort_output.is_some() {
+        eprintln!("grep: --xml cannot be combined with --sort-output-by");
+        return Err(());
+    }
+
+    if template_file.is_some() && (user_config.csv_output || user_config.xml_output) {
+        eprintln!("grep: --template cannot be combined with --csv or --xml");
+        return Err(());
+    }
+    if let Some(template_path) = &template_file {
+        user_config.output_template = Some(read_template_file(template_path)?);
+    }
+
+    if let Some(pattern_file) = &user_config.exclude_patterns_file {
+        // The entries are plain strings, found anywhere in a line like a literal pattern (no regex or glob syntax)
+        let exclude_strings = read_pattern_lines(pattern_file)?;
+        let build_result = aho_corasick::AhoCorasick::builder()
+            .ascii_case_insensitive(user_config.case_insensitive)
+            .build(&exclude_strings);
+        match build_result {
+            Ok(exclude_automaton) => user_config.exclude_patterns = Some(exclude_automaton),
+            Err(build_error) => {
+                eprintln!("grep: {}: {}", pattern_file, build_error);
+                return Err(());
+            }
+        }
+    }
+
+    if let Some(extension_file) = &binary_extension_file {
+        user_config.binary_extensions.extend(read_extension_file(extension_file)?);
+    }
+
+    if default_junk_dirs {
+        user_config.junk_dirs.extend(DEFAULT_JUNK_DIRS.iter().filter_map(|junk_dir| glob::Pattern::new(junk_dir).ok()));
+    }
+
+    // Printing every line only makes sense when selected lines are printed
+    if user_config.invert_match || user_config.count_matches || user_config.files_with_matches || user_config.files_without_match {
+        user_config.print_non_matching = false;
+    }
+
+    let color_depth = color_depth.unwrap_or_else(|| if terminal_supports_truecolor() { ColorDepth::TrueColor } else { ColorDepth::Basic });
+    if let Some(color_name) = &match_color_name {
+        user_config.color_palette.match_color = Some(parse_color(color_name, color_depth)?);
+    }
+    if let Some(color_name) = &line_number_color_name {
+        user_config.color_palette.line_number_color = parse_color(color_name, color_depth)?;
+    }
+
+    // --underline takes the place of the default match color
+    if user_config.underline_match && match_color_name.is_none() {
+        user_config.color_palette.match_color = None;
+    }
+
+    if user_config.tee_output && user_config.output_file.is_none() {
+        eprintln!("grep: --tee can only be used together with --output-file");
+        return Err(());
+    }
+
+    if let Some(exclude_file) = &user_config.exclude_from {
+        user_config.exclude_globs = read_glob_file(exclude_file)?;
+    }
+    if let Some(include_file) = &user_config.include_from {
+        user_config.include_globs.extend(read_glob_file(include_file)?);
+    }
+
+    // NO_COLOR (https://no-color.org) turns colors off unless the user explicitly asked for them
+    if env::var_os("NO_COLOR").is_some() && user_config.color_mode != ColorMode::Always {
+        user_config.color_mode = ColorMode::Never;
+        user_config.colored_output = false;
+    }
+
+    // Color codes would end up as garbage in the output file
+    if user_config.output_file.is_some() && user_config.color_mode != ColorMode::Always {
+        user_config.colored_output = false;
+    }
+
+    // --pretty is only meant for people looking at a terminal, keep the output parsable otherwise
+    if user_config.output_file.is_some() || user_config.csv_output || user_config.xml_output
+        || user_config.output_template.is_some()
+        || !io::stdout().is_terminal()
+    {
+        user_config.pretty = false;
+    }
+
+    // --parallel files are searched on their own, the --pretty headers and --rate-limit need the one search loop
+    if user_config.parallel_search && (user_config.pretty || user_config.rate_limit.is_some() || user_config.async_io) {
+        eprintln!("grep: --parallel can't be combined with --pretty, --rate-limit or --async");
+        return Err(());
+    }
+
+    // Links are part of the colored output, without a choice the terminal decides
+    // -c and --color=auto only print colors into a terminal, the links have to stay out of pipes as well
+    let colors_printed = match user_config.color_mode {
+        ColorMode::Never => false,
+        ColorMode::Auto => colored::control::SHOULD_COLORIZE.should_colorize(),
+        ColorMode::Always => true,
+    };
+    let hyperlinks_wanted = hyperlinks_choice.unwrap_or_else(terminal_supports_hyperlinks);
+    user_config.hyperlinks = user_config.colored_output && colors_printed && hyperlinks_wanted;
+
+    let similarity_search =
+        similarity_metric.is_some() || similarity_threshold.is_some() || jaro_prefix_scale.is_some() || jaro_prefix_length.is_some();
+    // These compare the pattern as plain text, so it is only a regex if -G, -E or -P says so
+    let text_pattern = base64_pattern
+        || hex_pattern
+        || url_encode_pattern
+        || word_list_file.is_some()
+        || similarity_search
+        || soundex_search
+        || fuzzy_distance.is_some();
+
+    if user_config.fixed_strings {
+        // -F the pattern is never compiled, metacharacters are searched as they are
+        pattern_syntax = PatternSyntax::Literal;
+    } else if auto_engine {
+        // --engine=auto keep the fast literal search when the pattern has nothing regex-specific,
+        // PCRE2 is only used for what the regex crate can't do (and only if it was built in)
+        pattern_syntax = if text_pattern || detect_literal(&user_config.pattern) {
+            PatternSyntax::Literal
+        } else if cfg!(feature = "pcre2") && needs_pcre2(&user_config.pattern) {
+            PatternSyntax::Perl
+        } else {
+            PatternSyntax::Extended
+        };
+    }
+
+    if !char_classes.is_empty() {
+        if pattern_syntax == PatternSyntax::Literal {
+            eprintln!("grep: --char-class needs a regex pattern, use it together with -G, -E or -P");
+            return Err(());
+        }
+        user_config.pattern = expand_char_classes(&user_config.pattern, &char_classes, &mut Vec::new())?;
+    }
+    if user_config.multiline && pattern_syntax == PatternSyntax::Literal {
+        // A literal pattern can't span lines, only regex patterns can use \n
+        eprintln!("grep: --multiline needs a regex pattern, use it together with -G, -E or -P");
+        return Err(());
+    }
+
+    if url_encode_pattern {
+        if pattern_syntax != PatternSyntax::Literal || hex_pattern || base64_pattern {
+            eprintln!("grep: --url-encode only works with literal patterns");
+            return Err(());
+        }
+        user_config.pattern = urlencoding::encode(&user_config.pattern).into_owned();
+        if io::stderr().is_terminal() {
+            eprintln!("grep: --url-encode: searching for {}", user_config.pattern);
+        }
+    }
+
+    let regex_options = RegexOptions { multi_line: user_config.multi_line, dot_all: user_config.dot_all };
+    let requested_kinds: Vec<PatternKind> = [
+        (base64_pattern, PatternKind::Base64),
+        (hex_pattern, PatternKind::HexBytes),
+        (word_list_file.is_some(), PatternKind::WordList),
+        (similarity_search, PatternKind::Similarity),
+        (soundex_search, PatternKind::Soundex),
+        (fuzzy_distance.is_some(), PatternKind::Fuzzy),
+    ]
+    .into_iter()
+    .filter_map(|(is_requested, pattern_kind)| is_requested.then_some(pattern_kind))
+    .collect();
+    let has_other_kind = |pattern_kind| has_other_pattern_kind(pattern_kind, &requested_kinds, pattern_syntax, capture_group.is_some());
+
+    if base64_pattern {
+        if has_other_kind(PatternKind::Base64) {
+            eprintln!("grep: --base64 can't be combined with other kinds of patterns");
+            return Err(());
+        }
+        user_config.search_engine = build_base64_engine(&user_config.pattern)?;
+    } else if hex_pattern {
+        if has_other_kind(PatternKind::HexBytes) {
+            eprintln!("grep: --hex-pattern can't be combined with other kinds of patterns");
+            return Err(());
+        }
+        let Some(pattern_bytes) = parse_hex_pattern(&user_config.pattern) else {
+            eprintln!("grep: invalid hex pattern '{}' (expected pairs of hex digits, e.g. DEADBEEF)", user_config.pattern);
+            return Err(());
+        };
+        user_config.search_engine = SearchEngine::HexBytes(pattern_bytes);
+    } else if let Some(word_file) = &word_list_file {
+        if has_other_kind(PatternKind::WordList) {
+            eprintln!("grep: --word-list only works with literal words");
+            return Err(());
+        }
+        user_config.search_engine = build_word_list_engine(word_file, user_config.case_insensitive)?;
+    } else if similarity_search {
+        // The pattern is a word to compare with the words of each line
+        if has_other_kind(PatternKind::Similarity) {
+            eprintln!("grep: --similarity-metric only works with literal patterns");
+            return Err(());
+        }
+        let mut similarity_metric = similarity_metric
+            .unwrap_or(SimilarityMetric::JaroWinkler { prefix_scale: DEFAULT_PREFIX_SCALE, max_prefix_length: DEFAULT_MAX_PREFIX_LENGTH });
+        if jaro_prefix_scale.is_some() || jaro_prefix_length.is_some() {
+            let SimilarityMetric::JaroWinkler { prefix_scale, max_prefix_length } = &mut similarity_metric else {
+                eprintln!("grep: --prefix-scale and --prefix-length only apply to --similarity-metric=jaro-winkler");
+                return Err(());
+            };
+            *prefix_scale = jaro_prefix_scale.unwrap_or(*prefix_scale);
+            *max_prefix_length = jaro_prefix_length.unwrap_or(*max_prefix_length);
+            // A longer prefix times a bigger scale could push the score over 1.0
+            if *prefix_scale * *max_prefix_length as f64 > 1.0 {
+                eprintln!("grep: --prefix-scale times --prefix-length must not be more than 1.0");
+                return Err(());
+            }
+        }
+        user_config.search_engine = SearchEngine::Similarity { similarity_metric, threshold: similarity_threshold.unwrap_or(0.9) };
+    } else if soundex_search {
+        // The pattern is a single word to compare with the words of each line
+        if has_other_kind(PatternKind::Soundex) {
+            eprintln!("grep: --soundex only works with literal patterns");
+            return Err(());
+        }
+        if user_config.pattern.is_empty() || !user_config.pattern.chars().all(|pattern_char| pattern_char.is_ascii_alphabetic()) {
+            eprintln!("grep: --soundex needs a single English word as the pattern");
+            return Err(());
+        }
+        let pattern_code = soundex::american_soundex(&user_config.pattern);
+        user_config.search_engine = SearchEngine::Soundex { pattern_code };
+    } else if let Some(max_distance) = fuzzy_distance {
+        // The pattern is compared as plain text, it can't also be a regex
+        if has_other_kind(PatternKind::Fuzzy) {
+            eprintln!("grep: --fuzzy only works with literal patterns");
+            return Err(());
+        }
+        let pattern_length = user_config.pattern.chars().count();
+        if max_distance >= pattern_length {
+            eprintln!("grep: --fuzzy distance must be smaller than the pattern length ({})", pattern_length);
+            return Err(());
+        }
+        if pattern_length > FUZZY_PATTERN_WARN_LENGTH {
+            eprintln!("grep: warning: --fuzzy with a pattern of {} characters can be very slow", pattern_length);
+        }
+        user_config.search_engine = SearchEngine::Fuzzy { max_distance };
+    } else if pattern_syntax == PatternSyntax::Perl {
+        let perl_pattern = anchor_to_line(&user_config.pattern, user_config.line_regexp);
+        user_config.search_engine = build_pcre2_engine(&perl_pattern, user_config.case_insensitive, regex_options, capture_group)?;
+    } else if pattern_syntax == PatternSyntax::Literal {
+        if capture_group.is_some() {
+            eprintln!("grep: --capture needs a regex pattern, use it together with -G, -E or -P");
+            return Err(());
+        }
+    } else {
+        let compiled_regex = if pattern_syntax == PatternSyntax::Basic {
+            compile_basic_regexp(&user_config.pattern, user_config.case_insensitive, user_config.line_regexp, regex_options)?
+        } else {
+            let extended_pattern = anchor_to_line(&user_config.pattern, user_config.line_regexp);
+            compile_regex(&extended_pattern, user_config.case_insensitive, regex_options)?
+        };
+        user_config.search_engine = match capture_group {
+            Some(capture_group) => {
+                // Like with --pcre2, the group must exist in the compiled pattern
+                if !compiled_regex.capture_names().any(|capture_name| capture_name == Some(capture_group.as_str())) {
+                    eprintln!("grep: the pattern has no capture group named '{}'", capture_group);
+                    return Err(());
+                }
+                SearchEngine::CaptureRegexp { compiled_regex, capture_group }
+            }
+            None if pattern_syntax == PatternSyntax::Basic => SearchEngine::BasicRegexp(compiled_regex),
+            None => SearchEngine::ExtendedRegexp(compiled_regex),
+        };
+    }
+
+    Ok(user_config)
+}
+
+// --pager=auto only pages when a person is looking at the output
+// MYGREP_PAGER takes precedence over the general PAGER variable
+fn default_pager() -> Option<String> {
+    if !io::stdout().is_terminal() {
+        return None;
+    }
+    env::var("MYGREP_PAGER")
+        .or_else(|_| env::var("PAGER"))
+        .ok()
+        .filter(|pager_command| !pager_command.trim().is_empty())
+}
+
+// Start from the default colors and apply MYGREP_COLORS (mt=01;31:fn=35:ln=32:se=36)
+// Command line color options are applied on top of this
+fn palette_from_env() -> ColorPalette {
+    let mut color_palette = ColorPalette::default();
+    let Ok(color_settings) = env::var("MYGREP_COLORS") else {
+        return color_palette;
+    };
+
+    for color_setting in color_settings.split(':').filter(|color_setting| !color_setting.is_empty()) {
+        let Some((color_key, sgr_codes)) = color_setting.split_once('=') else {
+            eprintln!("grep: warning: ignoring '{}' in MYGREP_COLORS", color_setting);
+            continue;
+        };
+        let is_valid_sgr = !sgr_codes.is_empty() && sgr_codes.chars().all(|sgr_char| sgr_char.is_ascii_digit() || sgr_char == ';');
+        if !is_valid_sgr {
+            eprintln!("grep: warning: ignoring '{}' in MYGREP_COLORS", color_setting);
+            continue;
+        }
+        let color_spec = ColorSpec::Sgr(sgr_codes.to_string());
+        match color_key {
+            "mt" => color_palette.match_color = Some(color_spec),
+            "fn" => color_palette.file_name_color = Some(color_spec),
+            "ln" => color_palette.line_number_color = color_spec,
+            "se" => color_palette.separator_color = Some(color_spec),
+            _ => eprintln!("grep: warning: ignoring unknown key '{}' in MYGREP_COLORS", color_key),
+        }
+    }
+    color_palette
+}
+
+// Parse a color name, rgb(R,G,B) or #RRGGBB
+// RGB colors are reduced to the colors available at color_depth
+fn parse_color(color_name: &str, color_depth: ColorDepth) -> Result<ColorSpec, ()> {
+    let rgb_color = if let Some(hex_digits) = color_name.strip_prefix('#') {
+        parse_hex_color(hex_digits)
+    } else if let Some(rgb_values) = color_name.strip_prefix("rgb(").and_then(|rgb_values| rgb_values.strip_suffix(')')) {
+        parse_rgb_color(rgb_values)
+    } else {
+        return color_name.parse::<Color>().map(ColorSpec::Named).map_err(|()| eprintln!("grep: unknown color '{}'", color_name));
+    };
+
+    let Some((r, g, b)) = rgb_color else {
+        eprintln!("grep: invalid color '{}'", color_name);
+        return Err(());
+    };
+    // The colored crate would reduce a TrueColor again without COLORTERM, so 256 and 24-bit colors are written as SGR codes
+    match color_depth {
+        ColorDepth::Basic => Ok(ColorSpec::Named(nearest_basic_color(r, g, b))),
+        ColorDepth::Palette256 => Ok(ColorSpec::Sgr(format!("38;5;{}", nearest_palette_color(r, g, b)))),
+        ColorDepth::TrueColor => Ok(ColorSpec::Sgr(format!("38;2;{};{};{}", r, g, b))),
+    }
+}
+
+fn parse_hex_color(hex_digits: &str) -> Option<(u8, u8, u8)> {
+    if hex_digits.len() != 6 || !hex_digits.chars().all(|hex_char| hex_char.is_ascii_hexdigit()) {
+        return None;
+    }
+    let channel = |channel_start: usize| u8::from_str_radix(&hex_digits[channel_start..channel_start + 2], 16).ok();
+    Some((channel(0)?, channel(2)?, channel(4)?))
+}
+
+fn parse_rgb_color(rgb_values: &str) -> Option<(u8, u8, u8)> {
+    let channels: Vec<u8> = rgb_values
+        .split(',')
+        .map(|channel_text| channel_text.trim().parse::<u8>().ok())
+        .collect::<Option<Vec<u8>>>()?;
+    match channels[..] {
+        [r, g, b] => Some((r, g, b)),
+        _ => None,
+    }
+}
+
+// COLORTERM is how terminals advertise 24-bit color support
+fn terminal_supports_truecolor() -> bool {
+    env::var("COLORTERM").is_ok_and(|color_term| color_term == "truecolor" || color_term == "24bit")
+}
+
+// Pick the basic terminal color closest to an RGB value, using the xterm palette
+fn nearest_basic_color(r: u8, g: u8, b: u8) -> Color {
+    const BASIC_COLORS: [(Color, (u8, u8, u8)); 16] = [
+        (Color::Black, (0, 0, 0)),
+        (Color::Red, (205, 0, 0)),
+        (Color::Green, (0, 205, 0)),
+        (Color::Yellow, (205, 205, 0)),
+        (Color::Blue, (0, 0, 238)),
+        (Color::Magenta, (205, 0, 205)),
+        (Color::Cyan, (0, 205, 205)),
+        (Color::White, (229, 229, 229)),
+        (Color::BrightBlack, (127, 127, 127)),
+        (Color::BrightRed, (255, 0, 0)),
+        (Color::BrightGreen, (0, 255, 0)),
+        (Color::BrightYellow, (255, 255, 0)),
+        (Color::BrightBlue, (92, 92, 255)),
+        (Color::BrightMagenta, (255, 0, 255)),
+        (Color::BrightCyan, (0, 255, 255)),
+        (Color::BrightWhite, (255, 255, 255)),
+    ];
+
+    let distance = |(basic_r, basic_g, basic_b): (u8, u8, u8)| {
+        let channel_distance = |first: u8, second: u8| (i32::from(first) - i32::from(second)).pow(2);
+        channel_distance(r, basic_r) + channel_distance(g, basic_g) + channel_distance(b, basic_b)
+    };
+    BASIC_COLORS
+        .iter()
+        .min_by_key(|(_, basic_rgb)| distance(*basic_rgb))
+        .map(|(basic_color, _)| *basic_color)
+        .unwrap_or(Color::Red)
+}
+
+// Pick the xterm-256 color closest to an RGB value: from the 6x6x6 color cube (16-231) or the gray ramp (232-255)
+// The first 16 colors are left out, terminals often change them with their theme
+fn nearest_palette_color(r: u8, g: u8, b: u8) -> u8 {
+    const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+    let distance = |(palette_r, palette_g, palette_b): (u8, u8, u8)| {
+        let channel_distance = |first: u8, second: u8| (i32::from(first) - i32::from(second)).pow(2);
+        channel_distance(r, palette_r) + channel_distance(g, palette_g) + channel_distance(b, palette_b)
+    };
+    let nearest_level = |channel: u8| {
+        (0..CUBE_LEVELS.len()).min_by_key(|&level_index| (i32::from(CUBE_LEVELS[level_index]) - i32::from(channel)).abs()).unwrap_or(0)
+    };
+    let (cube_r, cube_g, cube_b) = (nearest_level(r), nearest_level(g), nearest_level(b));
+    let cube_index = 16 + 36 * cube_r + 6 * cube_g + cube_b;
+    let cube_rgb = (CUBE_LEVELS[cube_r], CUBE_LEVELS[cube_g], CUBE_LEVELS[cube_b]);
+
+    // The gray ramp goes from 8 to 238 in steps of 10
+    let average = (u32::from(r) + u32::from(g) + u32::from(b)) / 3;
+    let gray_step = (average.saturating_sub(3) / 10).min(23) as u8;
+    let gray_level = 8 + 10 * gray_step;
+    if distance((gray_level, gray_level, gray_level)) < distance(cube_rgb) {
+        232 + gray_step
+    } else {
+        cube_index as u8
+    }
+}
+
+// Guess whether the terminal understands OSC 8 hyperlinks
+fn terminal_supports_hyperlinks() -> bool {
+    if let Ok(terminal_program) = env::var("TERM_PROGRAM")
+        && matches!(terminal_program.as_str(), "iTerm.app" | "WezTerm" | "vscode")
+    {
+        return true;
+    }
+    // VTE based terminals support hyperlinks since VTE 0.50 (VTE_VERSION 5000)
+    env::var("VTE_VERSION")
+        .ok()
+        .and_then(|vte_version| vte_version.parse::<u32>().ok())
+        .is_some_and(|vte_version| vte_version >= 5000)
+}
+
+// Name of the --template file inside its minijinja environment
+const TEMPLATE_NAME: &str = "template";
+
+// Read the --template file and compile it once, before any file is searched
+fn read_template_file(template_path: &str) -> Result<minijinja::Environment<'static>, ()> {
+    let template_source = match std::fs::read_to_string(template_path) {
+        Ok(template_source) => template_source,
+        Err(read_error) => {
+            eprintln!("grep: {}: {}", template_path, read_error);
+            return Err(());
+        }
+    };
+
+    let mut template_env = minijinja::Environment::new();
+    if let Err(template_error) = template_env.add_template_owned(TEMPLATE_NAME, template_source) {
+        eprintln!("grep: {}: {}", template_path, template_error);
+        return Err(());
+    }
+    Ok(template_env)
+}
+
+// Read the entries of a list file (--binary-ext-list, --exclude-from, --include-from, --word-list,
+// --invert-pattern), one per line with surrounding whitespace trimmed
+// Blank lines and lines starting with # are ignored
+fn read_pattern_lines(list_file: &str) -> Result<Vec<String>, ()> {
+    let file_content = match std::fs::read_to_string(list_file) {
+        Ok(file_content) => file_content,
+        Err(read_error) => {
+            eprintln!("grep: {}: {}", list_file, read_error);
+            return Err(());
+        }
+    };
+    Ok(file_content
+        .lines()
+        .map(|list_line| list_line.trim())
+        .filter(|list_entry| !list_entry.is_empty() && !list_entry.starts_with('#'))
+        .map(|list_entry| list_entry.to_string())
+        .collect())
+}
+
+// Read the extensions for --binary-ext-list, the leading dot is optional
+fn read_extension_file(extension_file: &str) -> Result<Vec<String>, ()> {
+    Ok(read_pattern_lines(extension_file)?
+        .into_iter()
+        .map(|extension_text| extension_text.trim_start_matches('.').to_lowercase())
+        .collect())
+}
+
+// Parse a --size-min / --size-max value, K, M and G are powers of 1024
+fn parse_file_size(size_text: &str) -> Option<u64> {
+    let (number_text, size_unit) = match size_text.char_indices().last()? {
+        (unit_position, 'K' | 'k') => (&size_text[..unit_position], 1 << 10),
+        (unit_position, 'M' | 'm') => (&size_text[..unit_position], 1 << 20),
+        (unit_position, 'G' | 'g') => (&size_text[..unit_position], 1 << 30),
+        _ => (size_text, 1),
+    };
+    number_text.parse::<u64>().ok()?.checked_mul(size_unit)
+}
+
+// Parse --permission: octal digits, or comma separated clauses of who (u, g, o, a), + or - and r, w, x
+fn parse_permission_filter(permission_text: &str) -> Option<PermissionFilter> {
+    if !permission_text.is_empty() && permission_text.chars().all(|mode_char| mode_char.is_digit(8)) {
+        return u32::from_str_radix(permission_text, 8).ok().filter(|mode_bits| *mode_bits <= 0o7777).map(PermissionFilter::Exact);
+    }
+
+    let mut set_bits = 0;
+    let mut unset_bits = 0;
+    for mode_clause in permission_text.split(',') {
+        let operator_position = mode_clause.find(['+', '-'])?;
+        let (who_text, permission_part) = mode_clause.split_at(operator_position);
+        let mut who_mask = 0;
+        for who_char in who_text.chars() {
+            who_mask |= match who_char {
+                'u' => 0o700,
+                'g' => 0o070,
+                'o' => 0o007,
+                'a' => 0o777,
+                _ => return None,
+            };
+        }
+        // Without a who, the clause is about everyone, like chmod a+x
+        if who_mask == 0 {
+            who_mask = 0o777;
+        }
+
+        let mut permission_mask = 0;
+        for permission_char in permission_part[1..].chars() {
+            permission_mask |= match permission_char {
+                'r' => 0o444,
+                'w' => 0o222,
+                'x' => 0o111,
+                _ => return None,
+            };
+        }
+        if permission_mask == 0 {
+            return None;
+        }
+        if permission_part.starts_with('+') {
+            set_bits |= who_mask & permission_mask;
+        } else {
+            unset_bits |= who_mask & permission_mask;
+        }
+    }
+    Some(PermissionFilter::Symbolic { set_bits, unset_bits })
+}
+
+// Look up the UID for --user
+#[cfg(unix)]
+fn resolve_user_id(user_name: &str) -> Result<Option<u32>, ()> {
+    match nix::unistd::User::from_name(user_name) {
+        Ok(Some(user)) => Ok(Some(user.uid.as_raw())),
+        Ok(None) => {
+            eprintln!("grep: unknown user '{}'", user_name);
+            Err(())
+        }
+        Err(lookup_error) => {
+            eprintln!("grep: cannot look up user '{}': {}", user_name, lookup_error);
+            Err(())
+        }
+    }
+}
+
+// Files have no Unix owner here, so --user filters nothing
+#[cfg(not(unix))]
+fn resolve_user_id(_user_name: &str) -> Result<Option<u32>, ()> {
+    eprintln!("grep: warning: --user is only supported on Unix, ignoring it");
+    Ok(None)
+}
+
+// Modification time of a --newer-than / --older-than reference file
+fn modification_time(reference_file: &str) -> Result<SystemTime, ()> {
+    match std::fs::metadata(reference_file).and_then(|file_metadata| file_metadata.modified()) {
+        Ok(modified_time) => Ok(modified_time),
+        Err(metadata_error) => {
+            eprintln!("grep: {}: {}", reference_file, metadata_error);
+            Err(())
+        }
+    }
+}
+
+// Parse the --since date: RFC 3339, a date and time in local time, or a date meaning local midnight
+fn parse_since_date(date_text: &str) -> Result<SystemTime, ()> {
+    use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone};
+
+    if let Ok(since_time) = DateTime::parse_from_rfc3339(date_text) {
+        return Ok(SystemTime::from(since_time));
+    }
+    let local_date_time = NaiveDateTime::parse_from_str(date_text, "%Y-%m-%dT%H:%M:%S")
+        .ok()
+        .or_else(|| NaiveDate::parse_from_str(date_text, "%Y-%m-%d").ok().and_then(|since_date| since_date.and_hms_opt(0, 0, 0)));
+    // A local time that does not exist (skipped by a DST change) is rejected as well
+    match local_date_time.and_then(|local_date_time| Local.from_local_datetime(&local_date_time).earliest()) {
+        Some(since_time) => Ok(SystemTime::from(since_time)),
+        None => {
+            eprintln!("grep: invalid date '{}' (expected e.g. 2024-01-01 or 2024-01-01T00:00:00Z)", date_text);
+            Err(())
+        }
+    }
+}
+
+// Read glob patterns from a file, one per line
+fn read_glob_file(glob_file: &str) -> Result<Vec<glob::Pattern>, ()> {
+    let mut glob_patterns = Vec::new();
+    for glob_text in read_pattern_lines(glob_file)? {
+        match glob::Pattern::new(&glob_text) {
+            Ok(glob_pattern) => glob_patterns.push(glob_pattern),
+            Err(glob_error) => {
+                eprintln!("grep: {}: invalid glob '{}': {}", glob_file, glob_text, glob_error);
+                return Err(());
+            }
+        }
+    }
+    Ok(glob_patterns)
+}
+
+// Check whether a pattern can be searched literally, i.e. it has no regex metacharacters
+fn detect_literal(search_pattern: &str) -> bool {
+    const REGEX_METACHARACTERS: &str = "^$.[]()*+?{}\\|";
+    !search_pattern.chars().any(|pattern_char| REGEX_METACHARACTERS.contains(pattern_char))
+}
+
+// Check whether a pattern uses look-around or back-references, which only PCRE2 supports
+fn needs_pcre2(search_pattern: &str) -> bool {
+    const LOOK_AROUND_STARTS: [&str; 4] = ["(?=", "(?!", "(?<=", "(?<!"];
+    if LOOK_AROUND_STARTS.iter().any(|look_around_start| search_pattern.contains(look_around_start)) {
+        return true;
+    }
+    // \1 to \9, or a named back-reference like \k<name>
+    let mut pattern_chars = search_pattern.chars();
+    while let Some(pattern_char) = pattern_chars.next() {
+        if pattern_char == '\\'
+            && let Some(escaped_char) = pattern_chars.next()
+            && (matches!(escaped_char, '1'..='9') || (escaped_char == 'k' && pattern_chars.clone().next() == Some('<')))
+        {
+            return true;
+        }
+    }
+    false
+}
+
+// Compile the pattern for -G, reporting syntax errors to the user
+fn compile_basic_regexp(search_pattern: &str, ignore_case: bool, line_regexp: bool, regex_options: RegexOptions) -> Result<regex::Regex, ()> {
+    let translated_pattern = match translate_basic_regexp(search_pattern) {
+        Ok(translated_pattern) => translated_pattern,
+        Err(translate_error) => {
+            eprintln!("grep: invalid pattern: {}", translate_error);
+            return Err(());
+        }
+    };
+    let translated_pattern = anchor_to_line(&translated_pattern, line_regexp);
+    compile_regex(&translated_pattern, ignore_case, regex_options)
+}
+
+// Load the words for --word-list and build one automaton for all of them
+fn build_word_list_engine(word_file: &str, ignore_case: bool) -> Result<SearchEngine, ()> {
+    let listed_words = read_pattern_lines(word_file)?;
+
+    // Standard matching reports every listed word found, overlapping ones included,
+    // find_matches_in_line picks the leftmost longest of those that are whole words
+    let build_result = aho_corasick::AhoCorasick::builder()
+        .ascii_case_insensitive(ignore_case)
+        .match_kind(aho_corasick::MatchKind::Standard)
+        .build(&listed_words);
+    match build_result {
+        Ok(word_automaton) => Ok(SearchEngine::WordList(word_automaton)),
+        Err(build_error) => {
+            eprintln!("grep: {}: {}", word_file, build_error);
+            Err(())
+        }
+    }
+}
+
+// --base64 the encoding of the pattern depends on where it starts in the encoded data,
+// so there is a form for each of the 3 byte offsets in a 3 byte group
+// Characters that also hold bits of the bytes around the pattern are left out of each form
+fn base64_forms(pattern_text: &str) -> Vec<String> {
+    use base64::Engine;
+
+    let pattern_bytes = pattern_text.as_bytes();
+    let mut encoded_forms = Vec::new();
+    for leading_bytes in 0..3 {
+        let mut aligned_bytes = vec![0; leading_bytes];
+        aligned_bytes.extend_from_slice(pattern_bytes);
+        let encoded_text = base64::engine::general_purpose::STANDARD_NO_PAD.encode(&aligned_bytes);
+        // Each character holds 6 bits, only whole characters of the pattern's own bits are kept
+        let first_char = (leading_bytes * 8).div_ceil(6);
+        let last_char = aligned_bytes.len() * 8 / 6;
+        if first_char < last_char && !encoded_forms.iter().any(|encoded_form| encoded_form == &encoded_text[first_char..last_char]) {
+            encoded_forms.push(encoded_text[first_char..last_char].to_string());
+        }
+    }
+    encoded_forms
+}
+
+fn build_base64_engine(pattern_text: &str) -> Result<SearchEngine, ()> {
+    let encoded_forms = base64_forms(pattern_text);
+    if encoded_forms.is_empty() {
+        eprintln!("grep: --base64 needs a pattern");
+        return Err(());
+    }
+    // Say what is searched, the encoded text has nothing in common with the pattern
+    if io::stderr().is_terminal() {
+        eprintln!("grep: --base64: searching for '{}' as {}", pattern_text, encoded_forms.join(", "));
+    }
+    match aho_corasick::AhoCorasick::builder().match_kind(aho_corasick::MatchKind::LeftmostLongest).build(&encoded_forms) {
+        Ok(encoded_automaton) => Ok(SearchEngine::Base64(encoded_automaton)),
+        Err(build_error) => {
+            eprintln!("grep: --base64: {}", build_error);
+            Err(())
+        }
+    }
+}
+
+// Check whether the pattern kind being set up clashes with the rest of the command line:
+// a regex syntax, --capture, or any other requested kind
+fn has_other_pattern_kind(pattern_kind: PatternKind, requested_kinds: &[PatternKind], pattern_syntax: PatternSyntax, has_capture_group: bool) -> bool {
+    pattern_syntax != PatternSyntax::Literal
+        || has_capture_group
+        || requested_kinds.iter().any(|requested_kind| *requested_kind != pattern_kind)
+}
+
+// Standard Jaro-Winkler prefix scale and prefix length, see --prefix-scale and --prefix-length
+const DEFAULT_PREFIX_SCALE: f64 = 0.1;
+const DEFAULT_MAX_PREFIX_LENGTH: usize = 4;
+
+// --fuzzy compares every window of the line with the pattern, so its cost grows with the pattern length
+const FUZZY_PATTERN_WARN_LENGTH: usize = 32;
+
+// Replace each \k{NAME} of a --char-class NAME with its pattern, which is expanded the same way
+// Other \k{...} are left alone, for PCRE2 they are references to named groups
+// expanding_names holds the definitions being expanded, to catch one that ends up using itself
+fn expand_char_classes(pattern_text: &str, char_classes: &[(String, String)], expanding_names: &mut Vec<String>) -> Result<String, ()> {
+    let mut expanded_pattern = String::with_capacity(pattern_text.len());
+    let mut remaining_text = pattern_text;
+    while let Some(backslash_position) = remaining_text.find('\\') {
+        expanded_pattern.push_str(&remaining_text[..backslash_position]);
+        let escaped_text = &remaining_text[backslash_position..];
+        let class_reference = escaped_text
+            .strip_prefix("\\k{")
+            .and_then(|reference_text| reference_text.split_once('}'))
+            .and_then(|(class_name, after_reference)| {
+                let (_, class_pattern) = char_classes.iter().find(|(defined_name, _)| defined_name == class_name)?;
+                Some((class_name, class_pattern, after_reference))
+            });
+        let Some((class_name, class_pattern, after_reference)) = class_reference else {
+            // Copy the backslash with the character it escapes, so that \\k{NAME} stays as it is
+            let escape_length = escaped_text.chars().nth(1).map_or(1, |escaped_char| 1 + escaped_char.len_utf8());
+            expanded_pattern.push_str(&escaped_text[..escape_length]);
+            remaining_text = &escaped_text[escape_length..];
+            continue;
+        };
+
+        if expanding_names.iter().any(|expanding_name| expanding_name == class_name) {
+            eprintln!("grep: --char-class: circular definition: {} -> {}", expanding_names.join(" -> "), class_name);
+            return Err(());
+        }
+        expanding_names.push(class_name.to_string());
+        expanded_pattern.push_str(&expand_char_classes(class_pattern, char_classes, expanding_names)?);
+        expanding_names.pop();
+        remaining_text = after_reference;
+    }
+    expanded_pattern.push_str(remaining_text);
+    Ok(expanded_pattern)
+}
+
+// -x the regex has to cover the whole line, the group keeps alternatives inside the anchors
+fn anchor_to_line(regex_pattern: &str, line_regexp: bool) -> String {
+    if line_regexp {
+        format!("^(?:{})$", regex_pattern)
+    } else {
+        regex_pattern.to_string()
+    }
+}
+
+// How a regex treats the line endings of the text it searches, only makes a difference with -U
+// where the regex sees the whole file instead of a single line
+#[derive(Debug, Clone, Copy, Default)]
+struct RegexOptions {
+    // ^ and $ match at every line, also before \r\n
+    multi_line: bool,
+    // . also matches \n
+    dot_all: bool,
+}
+
+// Compile a pattern with the regex crate (-G after translation, -E as it is)
+fn compile_regex(regex_pattern: &str, ignore_case: bool, regex_options: RegexOptions) -> Result<regex::Regex, ()> {
+    let compile_result = regex::RegexBuilder::new(regex_pattern)
+        .case_insensitive(ignore_case)
+        .multi_line(regex_options.multi_line)
+        .dot_matches_new_line(regex_options.dot_all)
+        .crlf(regex_options.multi_line)
+        .build();
+    match compile_result {
+        Ok(compiled_regex) => Ok(compiled_regex),
+        Err(compile_error) => {
+            eprintln!("grep: invalid pattern: {}", compile_error);
+            Err(())
+        }
+    }
+}
+
+// Rewrite a POSIX basic regular expression (with the GNU \| \+ \? extensions) in the regex crate syntax
+// In BRE the grouping and repetition characters are literal unless escaped, the other way round from the regex crate
+fn translate_basic_regexp(basic_pattern: &str) -> Result<String, String> {
+    let pattern_chars: Vec<char> = basic_pattern.chars().collect();
+    let mut translated_pattern = String::with_capacity(basic_pattern.len() * 2);
+    // '*' and '^' are only special at the start of the pattern, a group or an alternative
+    let mut at_expression_start = true;
+    let mut char_index = 0;
+
+    while char_index < pattern_chars.len() {
+        let pattern_char = pattern_chars[char_index];
+        char_index += 1;
+        let expression_start = at_expression_start;
+        at_expression_start = false;
+
+        match pattern_char {
+            '\\' => {
+                let Some(&escaped_char) = pattern_chars.get(char_index) else {
+                    return Err("trailing backslash".to_string());
+                };
+                char_index += 1;
+                match escaped_char {
+                    '(' | '|' => {
+                        translated_pattern.push(escaped_char);
+                        at_expression_start = true;
+                    }
+                    ')' | '{' | '}' | '+' | '?' => translated_pattern.push(escaped_char),
+                    '<' => translated_pattern.push_str("\\b{start}"),
+                    '>' => translated_pattern.push_str("\\b{end}"),
+                    'w' | 'W' | 's' | 'S' | 'b' | 'B' => {
+                        translated_pattern.push('\\');
+                        translated_pattern.push(escaped_char);
+                    }
+                    '1'..='9' => return Err("back-references are not supported".to_string()),
+                    _ => push_escaped_char(&mut translated_pattern, escaped_char),
+                }
+            }
+            '(' | ')' | '{' | '}' | '|' | '+' | '?' => push_escaped_char(&mut translated_pattern, pattern_char),
+            '*' if expression_start => push_escaped_char(&mut translated_pattern, pattern_char),
+            '^' if expression_start => {
+                translated_pattern.push('^');
+                at_expression_start = true;
+            }
+            '^' => push_escaped_char(&mut translated_pattern, pattern_char),
+            '$' => {
+                // '$' is an anchor only at the end of the pattern, a group or an alternative
+                let at_expression_end = match pattern_chars.get(char_index) {
+                    None => true,
+                    Some('\\') => matches!(pattern_chars.get(char_index + 1), Some(')' | '|')),
+                    Some(_) => false,
+                };
+                if at_expression_end {
+                    translated_pattern.push('$');
+                } else {
+                    push_escaped_char(&mut translated_pattern, pattern_char);
+                }
+            }
+            '[' => char_index = translate_bracket_expression(&pattern_chars, char_index, &mut translated_pattern)?,
+            _ => translated_pattern.push(pattern_char),
+        }
+    }
+    Ok(translated_pattern)
+}
+
+// Copy a bracket expression starting after its '[', returning the index after its ']'
+// A backslash is an ordinary character inside POSIX brackets but an escape in the regex crate
+fn translate_bracket_expression(pattern_chars: &[char], mut char_index: usize, translated_pattern: &mut String) -> Result<usize, String> {
+    translated_pattern.push('[');
+    if pattern_chars.get(char_index) == Some(&'^') {
+        translated_pattern.push('^');
+        char_index += 1;
+    }
+    // A ']' right after the opening bracket is part of the set
+    if pattern_chars.get(char_index) == Some(&']') {
+        translated_pattern.push_str("\\]");
+        char_index += 1;
+    }
+
+    while let Some(&bracket_char) = pattern_chars.get(char_index) {
+        char_index += 1;
+        match bracket_char {
+            ']' => {
+                translated_pattern.push(']');
+                return Ok(char_index);
+            }
+            '[' if matches!(pattern_chars.get(char_index), Some(':' | '.' | '=')) => {
+                // Character classes like [:alpha:] are copied as they are
+                let class_delimiter = pattern_chars[char_index];
+                translated_pattern.push('[');
+                while let Some(&class_char) = pattern_chars.get(char_index) {
+                    translated_pattern.push(class_char);
+                    char_index += 1;
+                    if class_char == ']' && pattern_chars[char_index - 2] == class_delimiter {
+                        break;
+                    }
+                }
+            }
+            '\\' | '[' | '&' | '~' => push_escaped_char(translated_pattern, bracket_char),
+            _ => translated_pattern.push(bracket_char),
+        }
+    }
+    Err("unmatched [".to_string())
+}
+
+fn push_escaped_char(translated_pattern: &mut String, literal_char: char) {
+    translated_pattern.push_str(&regex::escape(literal_char.encode_utf8(&mut [0; 4])));
+}
+
+// Compile the pattern for --pcre2, reporting syntax errors to the user
+// A --capture group must exist in the compiled pattern
+#[cfg(feature = "pcre2")]
+fn build_pcre2_engine(
+    search_pattern: &str,
+    ignore_case: bool,
+    regex_options: RegexOptions,
+    capture_group: Option<String>,
+) -> Result<SearchEngine, ()> {
+    // Lines are always valid UTF-8, so match offsets stay on character boundaries
+    let compile_result = pcre2::bytes::RegexBuilder::new()
+        .caseless(ignore_case)
+        .multi_line(regex_options.multi_line)
+        .dotall(regex_options.dot_all)
+        .crlf(regex_options.multi_line)
+        .utf(true)
+        .build(search_pattern);
+    let pcre2_regex = match compile_result {
+        Ok(pcre2_regex) => pcre2_regex,
+        Err(compile_error) => {
+            eprintln!("grep: invalid pattern: {}", compile_error);
+            return Err(());
+        }
+    };
+
+    if let Some(group_name) = &capture_group {
+        let group_exists = pcre2_regex
+            .capture_names()
+            .iter()
+            .any(|capture_name| capture_name.as_ref() == Some(group_name));
+        if !group_exists {
+            eprintln!("grep: the pattern has no capture group named '{}'", group_name);
+            return Err(());
+        }
+    }
+    Ok(SearchEngine::Pcre2 { pcre2_regex, capture_group })
+}
+
+#[cfg(not(feature = "pcre2"))]
+fn build_pcre2_engine(_search_pattern: &str, _ignore_case: bool, _regex_options: RegexOptions, _capture_group: Option<String>) -> Result<SearchEngine, ()> {
+    eprintln!("grep: -P/--pcre2 is not available in this build");
+    eprintln!("grep: rebuild with `cargo build --features pcre2` or install with `cargo install --path . --features pcre2`");
+    Err(())
+}
+
+// Decides which files collect_files leaves out
+// Implement this to plug in a custom filter instead of the default junk file check
+trait FileFilter: Send + Sync {
+    fn should_skip(&self, path: &Path) -> bool;
+}
+
+// Skips the common junk files recognized by is_junk_file
+struct DefaultFilter;
+
+impl FileFilter for DefaultFilter {
+    fn should_skip(&self, path: &Path) -> bool {
+        is_junk_file(path)
+    }
+}
+
+// A file to search, or a path that could not be read with the reason
+type CollectedFile = Result<PathBuf, (PathBuf, io::Error)>;
+
+// Collect the list of files
+// Process the file and directory paths entered by the user
+// Directories are walked lazily, so the first files can be searched before the whole tree was visited
+// Missing operands and unreadable directories are passed on as errors, to be reported with the results
+// The iterator is Send, so that search_parallel can walk the tree on a thread of its own
+fn collect_files<'a>(config: &'a Config, file_filter: &'a dyn FileFilter) -> impl Iterator<Item = CollectedFile> + Send + 'a {
+    config.files.iter().flat_map(move |user_input_path| -> Box<dyn Iterator<Item = CollectedFile> + Send + 'a> {
+        let path = Path::new(user_input_path);
+        if path.is_dir() {
+            if config.directory_mode == DirectoryMode::Read && !config.recursive_directory {
+                // --directory=read the error from reading it tells the user why nothing was found
+                return Box::new(std::iter::once(Ok(path.to_path_buf())));
+            }
+            if !config.recursive_directory && config.directory_mode != DirectoryMode::Recurse {
+                return Box::new(std::iter::empty());
+            }
+            // Junk and --exclude-dir directories are pruned so that nothing below them is visited,
+            // the directory given on the command line is always searched
+            let walk_entries = WalkDir::new(path).follow_links(config.follow_symlinks).into_iter().filter_entry(|directory_entry| {
+                directory_entry.depth() == 0
+                    || !directory_entry.file_type().is_dir()
+                    || !(matches_any_glob(directory_entry.path(), &config.junk_dirs)
+                        || matches_any_glob(directory_entry.path(), &config.exclude_dirs))
+            });
+            Box::new(walk_entries.filter_map(move |entry_result| {
+                let directory_entry = match entry_result {
+                    Ok(directory_entry) => directory_entry,
+                    Err(walk_error) => {
+                        // WalkDir does not descend into a link cycle, let the user know it was skipped
+                        if walk_error.loop_ancestor().is_some() {
+                            if !config.suppress_errors
+                                && let Some(loop_path) = walk_error.path()
+                            {
+                                eprintln!("warning: cycle detected at {}", loop_path.display());
+                            }
+                            return None;
+                        }
+                        let error_path = walk_error.path().unwrap_or(path).to_path_buf();
+                        let walk_error = walk_error.into_io_error().unwrap_or_else(|| io::Error::other("cannot be read"));
+                        return Some(Err((error_path, walk_error)));
+                    }
+                };
+                let file_path = directory_entry.path();
+                (has_selected_file_kind(file_path, Some(directory_entry.file_type()), config) && is_selected_file(file_path, config, file_filter))
+                    .then(|| Ok(file_path.to_path_buf()))
+            }))
+        } else if has_selected_file_kind(path, None, config) && is_selected_file(path, config, file_filter) {
+            Box::new(std::iter::once(Ok(path.to_path_buf())))
+        } else if let Err(metadata_error) = std::fs::metadata(path) {
+            // A file on the command line that does not exist (or can't be looked at)
+            Box::new(std::iter::once(Err((path.to_path_buf(), metadata_error))))
+        } else {
+            Box::new(std::iter::empty())
+        }
+    })
+}
+
+// Check the kind of a candidate against --type, without it only regular files (or links to them) are searched
+// Links are only seen as links when they are not followed
+fn has_selected_file_kind(file_path: &Path, known_file_type: Option<std::fs::FileType>, config: &Config) -> bool {
+    if config.file_kinds.is_empty() {
+        return file_path.is_file();
+    }
+    let file_type = match known_file_type {
+        Some(file_type) => file_type,
+        None => {
+            let file_metadata = if config.follow_symlinks { std::fs::metadata(file_path) } else { std::fs::symlink_metadata(file_path) };
+            let Ok(file_metadata) = file_metadata else {
+                return false;
+            };
+            file_metadata.file_type()
+        }
+    };
+
+    config.file_kinds.iter().any(|file_kind| match file_kind {
+        FileKind::Regular => file_type.is_file(),
+        // Only --files can list directories, they have no lines to search
+        FileKind::Directory => file_type.is_dir() && config.list_files,
+        FileKind::Symlink => file_type.is_symlink(),
+        #[cfg(unix)]
+        special_kind => {
+            use std::os::unix::fs::FileTypeExt;
+            match special_kind {
+                FileKind::BlockDevice => file_type.is_block_device(),
+                FileKind::CharDevice => file_type.is_char_device(),
+                FileKind::Fifo => file_type.is_fifo(),
+                _ => file_type.is_socket(),
+            }
+        }
+        // Devices, FIFOs and sockets are Unix file types
+        #[cfg(not(unix))]
+        _ => false,
+    })
+}
+
+// Check a candidate file against the file filter and the user's inclusion and exclusion globs
+// Without inclusion globs every file is included
+fn is_selected_file(file_path: &Path, config: &Config, file_filter: &dyn FileFilter) -> bool {
+    if file_filter.should_skip(file_path)
+        || matches_any_glob(file_path, &config.exclude_globs)
+        || has_binary_extension(file_path, &config.binary_extensions)
+    {
+        return false;
+    }
+    (config.include_globs.is_empty() || matches_any_glob(file_path, &config.include_globs))
+        && (config.iname_globs.is_empty() || matches_any_iname_glob(file_path, &config.iname_globs))
+        && (config.path_globs.is_empty() || matches_any_path_component(file_path, &config.path_globs))
+        && matches_metadata_filters(file_path, config)
+}
+
+// --path compares every named component of the path, "." and ".." are left out
+fn matches_any_path_component(file_path: &Path, path_globs: &[glob::Pattern]) -> bool {
+    file_path.components().any(|path_component| match path_component {
+        std::path::Component::Normal(component_name) => {
+            let component_name = component_name.to_string_lossy();
+            path_globs.iter().any(|glob_pattern| glob_pattern.matches(&component_name))
+        }
+        _ => false,
+    })
+}
+
+// --iname compares the lowercased file name only (the patterns are lowercased when parsed)
+fn matches_any_iname_glob(file_path: &Path, iname_globs: &[glob::Pattern]) -> bool {
+    let Some(file_name) = file_path.file_name() else {
+        return false;
+    };
+    let file_name = file_name.to_string_lossy().to_lowercase();
+    iname_globs.iter().any(|glob_pattern| glob_pattern.matches(&file_name))
+}
+
+// Filters on the file's metadata (--newer-than, --older-than, --since, --user, --size-min, --size-max, --permission),
+// the metadata is only read if one of them is set
+fn matches_metadata_filters(file_path: &Path, config: &Config) -> bool {
+    let filters_modified_time = config.modified_after.is_some() || config.modified_before.is_some();
+    let filters_size = config.size_min.is_some() || config.size_max.is_some();
+    if !filters_modified_time && !filters_size && config.owner_uid.is_none() && config.permission_filter.is_none() {
+        return true;
+    }
+    let Ok(file_metadata) = std::fs::metadata(file_path) else {
+        return false;
+    };
+
+    let file_size = file_metadata.len();
+    if config.size_min.is_some_and(|size_min| file_size < size_min) || config.size_max.is_some_and(|size_max| file_size > size_max) {
+        return false;
+    }
+
+    if filters_modified_time {
+        let Ok(modified_time) = file_metadata.modified() else {
+            return false;
+        };
+        let in_time_range = config.modified_after.is_none_or(|modified_after| modified_time > modified_after)
+            && config.modified_before.is_none_or(|modified_before| modified_time < modified_before);
+        if !in_time_range {
+            return false;
+        }
+    }
+
+    #[cfg(unix)]
+    if let Some(permission_filter) = config.permission_filter {
+        use std::os::unix::fs::PermissionsExt;
+        let permission_bits = file_metadata.permissions().mode() & 0o7777;
+        let has_permissions = match permission_filter {
+            PermissionFilter::Exact(exact_bits) => permission_bits == exact_bits,
+            PermissionFilter::Symbolic { set_bits, unset_bits } => permission_bits & set_bits == set_bits && permission_bits & unset_bits == 0,
+        };
+        if !has_permissions {
+            return false;
+        }
+    }
+
+    #[cfg(unix)]
+    if let Some(owner_uid) = config.owner_uid {
+        use std::os::unix::fs::MetadataExt;
+        return file_metadata.uid() == owner_uid;
+    }
+    true
+}
+
+// --smart-binary and --binary-ext-list compare extensions case-insensitively
+fn has_binary_extension(file_path: &Path, binary_extensions: &[String]) -> bool {
+    let Some(file_extension) = file_path.extension().and_then(|file_extension| file_extension.to_str()) else {
+        return false;
+    };
+    binary_extensions.iter().any(|binary_extension| binary_extension.eq_ignore_ascii_case(file_extension))
+}
+
+// A glob matches either the file name (e.g. *.log) or the whole path (e.g. build/*)
+fn matches_any_glob(file_path: &Path, glob_patterns: &[glob::Pattern]) -> bool {
+    let file_name = file_path.file_name().map(|file_name| file_name.to_string_lossy());
+    glob_patterns.iter().any(|glob_pattern| {
+        glob_pattern.matches_path(file_path)
+            || file_name.as_ref().is_some_and(|file_name| glob_pattern.matches(file_name))
+    })
+}
+
+// Bytes read from all searched files for --count-bytes
+static BYTES_READ: AtomicU64 = AtomicU64::new(0);
+
+// Extensions skipped by --smart-binary
+const DEFAULT_BINARY_EXTENSIONS: [&str; 30] = [
+    "o", "so", "a", "dylib", "dll", "exe", "lib", "obj", "class", "jar", "wasm", "pyc", "pdf", "png", "jpg", "jpeg", "gif",
+    "bmp", "ico", "webp", "zip", "gz", "tgz", "bz2", "xz", "7z", "rar", "mp3", "mp4", "woff",
+];
+
+// Directories skipped during -r unless --no-junk-dirs is given
+const DEFAULT_JUNK_DIRS: [&str; 5] = [".git", "__pycache__", "node_modules", ".svn", "target"];
+
+// Filter some common "junk files"
+fn is_junk_file(file_path: &Path) -> bool {
+    // Get the file name. If it is successfully obtained and can be converted into a string, check whether it is a junk file.
+    if let Some(file_name) = file_path.file_name()
+        && let Some(file_name_str) = file_name.to_str()
+    {
+        if file_name_str.starts_with("._") {
+            return true;
+        }
+        if file_name_str == ".DS_Store" {
+            return true;
+        }
+    }
+    false
+}
+
+// A piece of output kept in memory until it can be sorted (--sort-output-by)
+#[derive(Debug, Clone)]
+struct OutputRecord {
+    file_path: PathBuf,
+    line_number: usize,
+    matched_text: String,
+    rendered_text: String,
+    // A --pretty file name header, which is not sorted with the results (see finish)
+    is_file_header: bool,
+}
+
+// Receives everything the search prints and writes it to stdout or the --output-file
+// Results are written right away unless they have to be sorted first
+struct ResultPrinter {
+    writer: Box<dyn Write>,
+    sort_output: Option<SortBy>,
+    buffered_records: Vec<OutputRecord>,
+    // Something was written already, so a --pretty header needs a blank line in front of it
+    wrote_any: bool,
+    // A write to the writer failed, the search stops instead of reporting it for every file
+    write_failed: bool,
+    // The search_stream threads keep every record for the printer of the main thread
+    keep_records: bool,
+}
+
+impl ResultPrinter {
+    fn new(writer: Box<dyn Write>, sort_output: Option<SortBy>) -> ResultPrinter {
+        ResultPrinter {
+            writer,
+            sort_output,
+            buffered_records: Vec::new(),
+            wrote_any: false,
+            write_failed: false,
+            keep_records: false,
+        }
+    }
+
+    // A printer that writes nothing, its records are taken with into_records
+    fn collecting() -> ResultPrinter {
+        ResultPrinter {
+            keep_records: true,
+            ..ResultPrinter::new(Box::new(io::sink()), None)
+        }
+    }
+
+    fn into_records(self) -> Vec<OutputRecord> {
+        self.buffered_records
+    }
+
+    // Whether writing the results failed, e.g. because the reader of a pipe quit
+    fn has_write_failed(&self) -> bool {
+        self.write_failed
+    }
+
+    // rendered_text is written as is, so it has to include its line terminator
+    fn emit(&mut self, file_path: &Path, line_number: usize, matched_text: &str, rendered_text: String) -> io::Result<()> {
+        self.emit_record(OutputRecord {
+            file_path: file_path.to_path_buf(),
+            line_number,
+            matched_text: matched_text.to_string(),
+            rendered_text,
+            is_file_header: false,
+        })
+    }
+
+    // --pretty the file name in front of the results of a file, a blank line separates it from the previous file
+    fn emit_file_header(&mut self, file_path: &Path, header_text: String) -> io::Result<()> {
+        // Line 0 keeps it in front of the file's results when they are sorted by line
+        self.emit_record(OutputRecord {
+            file_path: file_path.to_path_buf(),
+            line_number: 0,
+            matched_text: String::new(),
+            rendered_text: header_text,
+            is_file_header: true,
+        })
+    }
+
+    fn emit_record(&mut self, output_record: OutputRecord) -> io::Result<()> {
+        if self.sort_output.is_none() && !self.keep_records {
+            let write_result = self.write_record(&output_record);
+            self.write_failed |= write_result.is_err();
+            return write_result;
+        }
+        self.buffered_records.push(output_record);
+        Ok(())
+    }
+
+    fn write_record(&mut self, output_record: &OutputRecord) -> io::Result<()> {
+        if output_record.is_file_header && self.wrote_any {
+            self.writer.write_all(b"\n")?;
+        }
+        self.wrote_any = true;
+        self.writer.write_all(output_record.rendered_text.as_bytes())
+    }
+
+    // Write the buffered results in the requested order, then the summary, and flush the writer
+    // Sorting is stable, so results with equal keys keep their search order
+    fn finish(mut self, summary_text: Option<String>) -> io::Result<()> {
+        match self.sort_output {
+            None => {}
+            Some(SortBy::File) => self.buffered_records.sort_by(|first, second| first.file_path.cmp(&second.file_path)),
+            Some(SortBy::Line) => {
+                // Results of one file are next to each other, only reorder inside each group
+                for file_records in self.buffered_records.chunk_by_mut(|first, second| first.file_path == second.file_path) {
+                    file_records.sort_by_key(|output_record| output_record.line_number);
+                }
+            }
+            Some(SortBy::Match) => {
+                // The headers are left out of the sort, each run of results of a file gets its header again
+                let (file_headers, mut result_records): (Vec<OutputRecord>, Vec<OutputRecord>) =
+                    std::mem::take(&mut self.buffered_records).into_iter().partition(|output_record| output_record.is_file_header);
+                result_records.sort_by(|first, second| first.matched_text.cmp(&second.matched_text));
+                for result_record in result_records {
+                    let starts_file_run = self
+                        .buffered_records
+                        .last()
+                        .is_none_or(|previous_record| previous_record.file_path != result_record.file_path);
+                    if starts_file_run
+                        && let Some(file_header) = file_headers.iter().find(|file_header| file_header.file_path == result_record.file_path)
+                    {
+                        self.buffered_records.push(file_header.clone());
+                    }
+                    self.buffered_records.push(result_record);
+                }
+            }
+        }
+        for output_record in std::mem::take(&mut self.buffered_records) {
+            self.write_record(&output_record)?;
+        }
+        if let Some(summary_text) = summary_text {
+            self.writer.write_all(summary_text.as_bytes())?;
+        }
+        self.writer.flush()
+    }
+}
+
+// Search for matching lines in a single file and print the results
+// Returns whether at least one line was selected, None when -I skipped the file as binary
+fn search_file(file_path: &Path, config: &Config, printer: &mut ResultPrinter) -> io::Result<Option<bool>> {
+    let file = File::open(file_path)?;
+    let Some(input_limit) = config.input_limit else {
+        return search_reader(BufReader::new(file), file_path, config, printer);
+    };
+
+    // --input-limit also keeps endless files like /dev/zero from being read forever
+    // One byte more than the limit is read from the file, the search itself stops at the limit
+    let file_length = file.metadata().ok().filter(|file_metadata| file_metadata.is_file()).map(|file_metadata| file_metadata.len());
+    let mut limited_reader = BufReader::new(file.take(input_limit + 1));
+    let mut searched_reader = (&mut limited_reader).take(input_limit);
+    let found_selected_line = search_reader(&mut searched_reader, file_path, config, printer)?;
+    // The extra byte usually came with the last read of the search, reading again just for it could block
+    // on a FIFO or an endless stream like /proc/kmsg, a regular file tells its length instead
+    let input_truncated = searched_reader.limit() == 0
+        && (!limited_reader.buffer().is_empty() || file_length.is_some_and(|file_length| file_length > input_limit));
+    if !config.suppress_errors && input_truncated {
+        warn_input_limit(file_path, input_limit);
+    }
+    Ok(found_selected_line)
+}
+
+fn warn_input_limit(file_path: &Path, input_limit: u64) {
+    eprintln!("grep: warning: {}: only the first {} bytes were searched (--input-limit)", file_path.display(), input_limit);
+}
+
+// Search the content of a file that has already been opened
+fn search_reader<R: BufRead>(mut reader: R, file_path: &Path, config: &Config, printer: &mut ResultPrinter) -> io::Result<Option<bool>> {
+    if !config.multiline {
+        return search_lines(reader, file_path, config, printer, None);
+    }
+    // -U the regex runs over the whole file once, the lines are then printed as usual with their part of the matches
+    let mut file_bytes = Vec::new();
+    if let Some(multiline_limit) = config.multiline_limit {
+        // --multiline-limit one byte more than the limit tells whether the file is longer
+        (&mut reader).take(multiline_limit as u64 + 1).read_to_end(&mut file_bytes)?;
+        if file_bytes.len() > multiline_limit {
+            if !config.suppress_errors {
+                eprintln!(
+                    "grep: warning: {}: longer than {} bytes, searched line by line (--multiline-limit)",
+                    file_path.display(),
+                    multiline_limit
+                );
+            }
+            // The bytes already read are searched first, then the rest of the file
+            return search_lines(BufReader::new(io::Cursor::new(file_bytes).chain(reader)), file_path, config, printer, None);
+        }
+    } else {
+        reader.read_to_end(&mut file_bytes)?;
+    }
+    let line_matches = find_multiline_matches(&file_bytes, config);
+    search_lines(BufReader::new(io::Cursor::new(file_bytes)), file_path, config, printer, Some(line_matches))
+}
+
+// Run the regex over the whole file and split the matches into the lines they cover
+// Returns the matches of each line, with offsets into the line text as line_from_bytes makes it
+fn find_multiline_matches(file_bytes: &[u8], config: &Config) -> Vec<Vec<(usize, usize)>> {
+    let mut file_text = String::from_utf8_lossy(file_bytes).into_owned();
+    if config.escape_nul_bytes {
+        file_text = file_text.replace('\0', "\\0");
+    }
+
+    // Where the text of each line starts and ends, without the line ending
+    let mut line_ranges = Vec::new();
+    let mut line_start = 0;
+    for line_text in file_text.split_inclusive('\n') {
+        let line_content = match line_text.strip_suffix('\n') {
+            Some(line_content) => line_content.strip_suffix('\r').unwrap_or(line_content),
+            None => line_text,
+        };
+        line_ranges.push((line_start, line_start + line_content.len()));
+        line_start += line_text.len();
+    }
+
+    let mut line_matches = vec![Vec::new(); line_ranges.len()];
+    // --match-limit is about the matches of a line, the whole file is searched here
+    let file_matches = find_matches_in_line(&file_text, &config.pattern, config.case_insensitive, config.line_regexp, &config.search_engine, None);
+    for (match_start, match_end) in file_matches {
+        // The line the match starts on is always selected, even by an empty match or one that starts at the line ending
+        let first_line = line_ranges.partition_point(|&(_, line_end)| line_end < match_start);
+        let mut line_index = first_line;
+        while line_index < line_ranges.len() && (line_index == first_line || line_ranges[line_index].0 < match_end) {
+            let (line_start, line_end) = line_ranges[line_index];
+            let piece_start = match_start.max(line_start).min(line_end);
+            let piece_end = match_end.min(line_end).max(piece_start);
+            line_matches[line_index].push((piece_start - line_start, piece_end - line_start));
+            line_index += 1;
+        }
+    }
+    line_matches
+}
+
+// Search a file line by line
+// line_matches holds the matches of every line when they were already found in the whole file (-U)
+fn search_lines<R: BufRead>(
+    mut reader: R,
+    file_path: &Path,
+    config: &Config,
+    printer: &mut ResultPrinter,
+    mut line_matches: Option<Vec<Vec<(usize, usize)>>>,
+) -> io::Result<Option<bool>> {
+    // From here on file_path is only used for display
+    let display_path = resolve_display_path(file_path, config);
+    let file_path = display_path.as_path();
+    let file_name_text = format_file_name(file_path, None, config);
+    // --filename-width only applies to the file name in front of each line
+    let file_column_text = format_file_name(file_path, config.filename_width, config);
+    let mut printed_file_header = false;
+    let mut warned_match_limit = false;
+    let mut current_line_number: usize = 0;
+    let mut found_selected_line = false;
+    let mut selected_line_count: usize = 0;
+    let mut selected_binary_line = false;
+
+    // Look at the first 8 KB once, before anything is printed, to decide whether the file is binary
+    // A single read can return less than that (pipes, -U chained readers), so the block is read in full
+    // -a forces binary files to be searched as text
+    let mut first_block = Vec::with_capacity(BINARY_CHECK_BLOCK_SIZE);
+    (&mut reader).take(BINARY_CHECK_BLOCK_SIZE as u64).read_to_end(&mut first_block)?;
+    let mut is_binary = !config.binary_as_text && is_binary_content(&first_block);
+    if is_binary && config.suppress_binary {
+        // -I skip binary files silently, -L doesn't list them either
+        return Ok(None);
+    }
+    // The rest of the file is buffered in blocks of the same size for --skip-binary-content
+    let mut checked_byte_count = first_block.len();
+    let mut reader = BufReader::with_capacity(BINARY_CHECK_BLOCK_SIZE, io::Cursor::new(first_block).chain(reader));
+
+    let mut line_bytes = Vec::new();
+    // --skip-last holds back the N most recent lines, whatever is left in here at the end of the file is not searched
+    let mut held_back_lines = std::collections::VecDeque::with_capacity(config.skip_last + 1);
+    loop {
+        // --skip-binary-content check each new block before its lines, so binary data later in the file
+        // switches to binary mode from the first line of its block on
+        let streaming_check = config.streaming_binary_check && !is_binary && !config.binary_as_text;
+        if streaming_check && checked_byte_count == 0 {
+            let next_block = reader.fill_buf()?;
+            checked_byte_count = next_block.len();
+            is_binary = is_binary_content(next_block);
+        }
+        let line_length = reader.read_until(b'\n', &mut line_bytes)?;
+        if line_length == 0 {
+            break;
+        }
+        if line_length > checked_byte_count {
+            // A line longer than the checked bytes went on into blocks that were not checked yet,
+            // what is left of the last of them is checked before the next line
+            is_binary |= streaming_check && is_binary_content(&line_bytes[line_bytes.len() - (line_length - checked_byte_count)..]);
+            checked_byte_count = 0;
+        } else {
+            checked_byte_count -= line_length;
+        }
+        if is_binary && streaming_check && config.suppress_binary {
+            break;
+        }
+
+        if config.count_bytes {
+            BYTES_READ.fetch_add(line_length as u64, Ordering::Relaxed);
+        }
+        if config.skip_last > 0 {
+            held_back_lines.push_back(std::mem::take(&mut line_bytes));
+            if held_back_lines.len() <= config.skip_last {
+                continue;
+            }
+            line_bytes = held_back_lines.pop_front().unwrap_or_default();
+        }
+        current_line_number += 1;
+
+        // --skip-first header lines are only counted, so -n keeps the real line numbers
+        if current_line_number <= config.skip_first {
+            line_bytes.clear();
+            continue;
+        }
+
+        // --sample-size the other lines are skipped without being decoded
+        if let Some(sample_size) = config.sample_size
+            && !current_line_number.is_multiple_of(sample_size)
+        {
+            line_bytes.clear();
+            continue;
+        }
+
+        // --hex-pattern is searched in the raw bytes, the text may have lost some to UTF-8 replacement
+        let mut found_matches = match &config.search_engine {
+            SearchEngine::HexBytes(pattern_bytes) => {
+                Some(find_hex_matches(&line_bytes, pattern_bytes, config.line_regexp, config.escape_nul_bytes))
+            }
+            _ => line_matches
+                .as_mut()
+                .map(|line_matches| line_matches.get_mut(current_line_number - 1).map(std::mem::take).unwrap_or_default()),
+        };
+        let mut line_content = line_from_bytes(&line_bytes);
+        // --hex-output dumps the bytes that were read, the text may have lost some to UTF-8 replacement
+        let raw_line_content = config.hex_output.then(|| strip_line_ending(&line_bytes).to_vec());
+        line_bytes.clear();
+        // --print-null-match escape before matching, so that match offsets fit the printed text
+        if config.escape_nul_bytes && line_content.contains('\0') {
+            line_content = line_content.replace('\0', "\\0");
+        }
+
+        if let Some(max_line_length) = config.max_line_length
+            && line_content.len() > max_line_length
+        {
+            if !config.truncate_long_lines {
+                if !config.suppress_errors {
+                    eprintln!(
+                        "grep: warning: {}:{}: skipping line of {} bytes (--max-line-length {})",
+                        file_path.display(),
+                        current_line_number,
+                        line_content.len(),
+                        max_line_length
+                    );
+                }
+                continue;
+            }
+            // --truncate-long-lines cut at the last character that fits
+            let mut truncated_length = max_line_length;
+            while !line_content.is_char_boundary(truncated_length) {
+                truncated_length -= 1;
+            }
+            line_content.truncate(truncated_length);
+        }
+
+        // --ignore-empty-lines blank lines are never selected, not even by -v
+        if config.skip_empty && line_content.trim().is_empty() {
+            continue;
+        }
+
+        let mut matches_found = match found_matches.take() {
+            Some(mut found_matches) => {
+                // --max-line-length --truncate-long-lines may have cut off the end of the line
+                // Empty matches are only found by -U regexes and still select the line
+                found_matches.retain(|&(match_start, match_end)| match_start < line_content.len() || match_start == match_end);
+                for (match_start, match_end) in found_matches.iter_mut() {
+                    *match_start = (*match_start).min(line_content.len());
+                    *match_end = (*match_end).min(line_content.len());
+                }
+                found_matches
+            }
+            // --match-limit one match more than the limit tells whether the line had more
+            None => find_matches_in_line(
+                &line_content,
+                &config.pattern,
+                config.case_insensitive,
+                config.line_regexp,
+                &config.search_engine,
+                config.match_limit.map(|match_limit| match_limit + 1),
+            ),
+        };
+
+        // --match-limit keeps lines with thousands of matches from blowing up the colored output
+        // The search of the line already stopped after one match more than the limit
+        if let Some(match_limit) = config.match_limit
+            && matches_found.len() > match_limit
+        {
+            if !warned_match_limit && !config.suppress_errors {
+                eprintln!(
+                    "grep: warning: {}:{}: only the first {} matches of a line are shown (--match-limit)",
+                    file_path.display(),
+                    current_line_number,
+                    match_limit
+                );
+                warned_match_limit = true;
+            }
+            matches_found.truncate(match_limit);
+        }
+
+        let mut should_print_line = if config.invert_match {
+            // Print this line only if no match is found
+            matches_found.is_empty()
+        } else {
+            // If a match is found, print the line
+            !matches_found.is_empty()
+        };
+
+        // --invert-pattern drop selected lines containing any of the excluded strings
+        if should_print_line
+            && let Some(exclude_patterns) = &config.exclude_patterns
+            && exclude_patterns.is_match(&line_content)
+        {
+            should_print_line = false;
+        }
+
+        if should_print_line && config.count_matches {
+            // --count only counts, nothing is printed per line
+            found_selected_line = true;
+            selected_line_count += 1;
+            continue;
+        }
+
+        if should_print_line && (config.files_with_matches || config.files_without_match || is_binary) {
+            // -l, -L and binary files only need to know that the file has a selected line
+            found_selected_line = true;
+            selected_binary_line = is_binary;
+            break;
+        }
+
+        // --print-non-matching-lines the other lines are printed too, but they do not count as selected
+        let passthrough_line = config.print_non_matching && !should_print_line && !is_binary;
+
+        if should_print_line || passthrough_line {
+            found_selected_line |= should_print_line;
+
+            // taking color output options into account
+            let text_to_print = if config.colored_output && (!matches_found.is_empty() || !passthrough_line) {
+                // -c Add red highlight to matching text
+                colorize_hits(&line_content, &matches_found, config)
+            } else if config.colored_output {
+                colorize_hits(&line_content.dimmed().to_string(), &[], config)
+            } else {
+                line_content.clone()
+            };
+
+            // --line-number-width pads before coloring, so the escape codes don't count
+            let line_number_text = format!("{:>width$}", current_line_number, width = config.line_number_width.unwrap_or(0));
+            // -c also colors the line number prefix
+            let line_number_text = if config.colored_output {
+                paint(&line_number_text, &config.color_palette.line_number_color)
+            } else {
+                line_number_text
+            };
+
+            if config.pretty && !printed_file_header {
+                // --pretty show the file name once as a header, separated from the previous file
+                printer.emit_file_header(file_path, format!("{}\n", file_name_text.magenta().bold()))?;
+                printed_file_header = true;
+            }
+            if config.xml_output && !printed_file_header {
+                // --xml open the <file> element at the first result, it is closed after the last line
+                let file_element = format!("  <file name=\"{}\">\n", quick_xml::escape::escape(file_path.display().to_string()));
+                printer.emit(file_path, current_line_number, "", file_element)?;
+                printed_file_header = true;
+            }
+
+            let separator_text = format_separator(if passthrough_line { "- " } else { &config.separator }, config);
+            let rendered_line = if let Some(template_env) = &config.output_template {
+                render_template_matches(template_env, file_path, &line_content, &matches_found, current_line_number)
+            } else if config.csv_output {
+                render_csv_rows(file_path, &line_content, &matches_found, current_line_number)
+            } else if config.xml_output {
+                render_xml_matches(&line_content, &matches_found, current_line_number)
+            } else if config.pretty {
+                render_pretty_line(&line_content, &matches_found, current_line_number, config)
+            } else {
+                let line_prefix = if config.print_filenames && config.line_numbers {
+                    // -f + -n Display file name and line number
+                    let filename_separator_text = match &config.filename_separator {
+                        Some(filename_separator) => format_separator(filename_separator, config),
+                        None => separator_text.clone(),
+                    };
+                    format!("{}{}{}{}", file_column_text, filename_separator_text, line_number_text, separator_text)
+                } else if config.print_filenames {
+                    // -f file name
+                    format!("{}{}", file_column_text, separator_text)
+                } else if config.line_numbers {
+                    // -n line number
+                    format!("{}{}", line_number_text, separator_text)
+                } else {
+                    // print text content
+                    String::new()
+                };
+                if config.hex_output {
+                    // --hex-output dumps the whole line, --max-columns and --column-width don't apply
+                    // The matches were found in the text, the dump needs them in the raw bytes
+                    let raw_content = raw_line_content.as_deref().unwrap_or_default();
+                    let dumped_bytes = &raw_content[..raw_offset(raw_content, line_content.len(), false, config.escape_nul_bytes)];
+                    let raw_matches: Vec<(usize, usize)> = matches_found
+                        .iter()
+                        .map(|&(match_start, match_end)| {
+                            (
+                                raw_offset(raw_content, match_start, false, config.escape_nul_bytes),
+                                raw_offset(raw_content, match_end, true, config.escape_nul_bytes),
+                            )
+                        })
+                        .collect();
+                    render_hex_dump(&line_prefix, dumped_bytes, &raw_matches, config)
+                } else {
+                    // --max-columns byte offset of the first character that is cut off
+                    let truncate_position = config
+                        .max_columns
+                        .and_then(|max_columns| line_content.char_indices().nth(max_columns))
+                        .map(|(truncate_position, _)| truncate_position);
+                    match (truncate_position, config.column_width) {
+                        (Some(truncate_position), column_width) => {
+                            let shown_matches = clip_matches(&matches_found, 0, truncate_position);
+                            render_truncated_line(&line_prefix, &line_content[..truncate_position], &shown_matches, column_width, config)
+                        }
+                        (None, Some(column_width)) => render_wrapped_line(&line_prefix, &line_content, &matches_found, column_width, config),
+                        (None, None) => format!("{}{}\n", line_prefix, text_to_print),
+                    }
+                }
+            };
+
+            // The first match is the sort key for --sort-output-by=match
+            let first_matched_text = match matches_found.first() {
+                Some(&(match_start, match_end)) => &line_content[match_start..match_end],
+                None => "",
+            };
+            let rendered_line = if config.color_reset_on_newline { reset_colors_at_line_ends(rendered_line) } else { rendered_line };
+            printer.emit(file_path, current_line_number, first_matched_text, rendered_line)?;
+        }
+    }
+
+    if config.xml_output && printed_file_header {
+        printer.emit(file_path, current_line_number, "", "  </file>\n".to_string())?;
+    }
+
+    if config.count_matches {
+        // --count-per-file also reports files without any selected line
+        if found_selected_line || config.count_all_files {
+            let count_text = if config.xml_output {
+                render_xml_file(file_path, Some(selected_line_count))
+            } else if config.csv_output {
+                render_csv_file(file_path, Some(selected_line_count))
+            } else {
+                format!("{}{}{}\n", file_name_text, format_separator(":", config), selected_line_count)
+            };
+            printer.emit(file_path, 0, "", count_text)?;
+        }
+    } else if config.files_with_matches {
+        // -l print the name of a file with a selected line
+        if found_selected_line {
+            print_file_name(file_path, config, printer)?;
+        }
+    } else if config.files_without_match {
+        // -L print the name of a file without any selected line
+        if !found_selected_line {
+            print_file_name(file_path, config, printer)?;
+        }
+    } else if selected_binary_line {
+        // Binary files only report that they match instead of printing lines
+        printer.emit(file_path, 0, "", format!("Binary file {} matches\n", file_name_text))?;
+    }
+    Ok(Some(found_selected_line))
+}
+
+// --dereference resolve symlinks so that links to the same file show the same name
+// Falls back to the path as given if it cannot be resolved
+fn resolve_display_path(file_path: &Path, config: &Config) -> PathBuf {
+    if config.dereference_paths
+        && let Ok(canonical_path) = file_path.canonicalize()
+    {
+        return canonical_path;
+    }
+    file_path.to_path_buf()
+}
+
+// Print a file name for -l / -L / --files, terminated by NUL with -Z or by a newline otherwise
+// -l and -L with --xml or --csv print an element or a row instead, --files output is never XML or CSV
+fn print_file_name(file_path: &Path, config: &Config, printer: &mut ResultPrinter) -> io::Result<()> {
+    if config.xml_output && !config.list_files {
+        return printer.emit(file_path, 0, "", render_xml_file(file_path, None));
+    }
+    if config.csv_output && !config.list_files {
+        return printer.emit(file_path, 0, "", render_csv_file(file_path, None));
+    }
+    let terminator = if config.null_terminated { '\0' } else { '\n' };
+    printer.emit(file_path, 0, "", format!("{}{}", format_file_name(file_path, None, config), terminator))
+}
+
+// File name as printed in results, wrapped in an OSC 8 link to the file with --hyperlinks
+// With a width the name is padded or cut to exactly that many characters, before it is colored
+fn format_file_name(file_path: &Path, width: Option<usize>, config: &Config) -> String {
+    let mut file_name_text = file_path.display().to_string();
+    if let Some(width) = width {
+        file_name_text = fit_to_width(&file_name_text, width);
+    }
+    if config.colored_output
+        && let Some(file_name_color) = &config.color_palette.file_name_color
+    {
+        file_name_text = paint(&file_name_text, file_name_color);
+    }
+    if !config.hyperlinks {
+        return file_name_text;
+    }
+    // Links need an absolute path
+    let absolute_path = match file_path.canonicalize() {
+        Ok(absolute_path) => absolute_path,
+        Err(_) => env::current_dir().map(|current_dir| current_dir.join(file_path)).unwrap_or_else(|_| file_path.to_path_buf()),
+    };
+    format!(
+        "\x1b]8;;file://{}\x1b\\{}\x1b]8;;\x1b\\",
+        percent_encode_path(&absolute_path),
+        file_name_text
+    )
+}
+
+// Pad text with spaces to width characters, or keep its end behind ... if it is longer
+fn fit_to_width(text: &str, width: usize) -> String {
+    let char_count = text.chars().count();
+    if char_count <= width {
+        return format!("{:<width$}", text, width = width);
+    }
+    // Too narrow for the ..., keep as much of the end as fits
+    let ellipsis = if width > 3 { "..." } else { "" };
+    let kept_chars = width - ellipsis.len();
+    let kept_text: String = text.chars().skip(char_count - kept_chars).collect();
+    format!("{}{}", ellipsis, kept_text)
+}
+
+// Separator between the parts of a result, colored by MYGREP_COLORS se= with -c
+fn format_separator(separator: &str, config: &Config) -> String {
+    match &config.color_palette.separator_color {
+        Some(separator_color) if config.colored_output => paint(separator, separator_color),
+        _ => separator.to_string(),
+    }
+}
+
+// --color-reset-on-newline end each output line that has color codes with a reset,
+// whatever the codes before it left switched on
+fn reset_colors_at_line_ends(rendered_text: String) -> String {
+    if !rendered_text.contains("\x1b[") {
+        return rendered_text;
+    }
+    let mut reset_text = String::with_capacity(rendered_text.len() + 8);
+    for output_line in rendered_text.split_inclusive('\n') {
+        match output_line.strip_suffix('\n') {
+            Some(line_text) if line_text.contains("\x1b[") => {
+                reset_text.push_str(line_text);
+                reset_text.push_str("\x1b[0m\n");
+            }
+            _ => reset_text.push_str(output_line),
+        }
+    }
+    reset_text
+}
+
+// Apply a color to text, unless colors are turned off (no terminal, NO_COLOR, --color=never)
+fn paint(text: &str, color_spec: &ColorSpec) -> String {
+    match color_spec {
+        ColorSpec::Named(color) => text.color(*color).to_string(),
+        ColorSpec::Sgr(sgr_codes) => {
+            if colored::control::SHOULD_COLORIZE.should_colorize() {
+                format!("\x1b[{}m{}\x1b[0m", sgr_codes, text)
+            } else {
+                text.to_string()
+            }
+        }
+    }
+}
+
+// Percent-encode everything in a path except unreserved URL characters and '/'
+fn percent_encode_path(file_path: &Path) -> String {
+    let mut encoded_path = String::new();
+    for &path_byte in file_path.to_string_lossy().as_bytes() {
+        if path_byte.is_ascii_alphanumeric() || b"-._~/".contains(&path_byte) {
+            encoded_path.push(path_byte as char);
+        } else {
+            encoded_path.push_str(&format!("%{:02X}", path_byte));
+        }
+    }
+    encoded_path
+}
+
+// Size of the blocks that are checked for binary content, the first one before anything of the file is printed
+const BINARY_CHECK_BLOCK_SIZE: usize = 8 * 1024;
+
+// A file is treated as binary if its first block contains a NUL byte
+fn is_binary_content(first_block: &[u8]) -> bool {
+    first_block.contains(&0)
+}
+
+// Convert a raw line into text, dropping the line ending
+// Invalid UTF-8 is replaced so that binary files searched with -a can still be printed
+fn line_from_bytes(line_bytes: &[u8]) -> String {
+    String::from_utf8_lossy(strip_line_ending(line_bytes)).into_owned()
+}
+
+// The bytes of a raw line without its \n or \r\n
+fn strip_line_ending(line_bytes: &[u8]) -> &[u8] {
+    let mut line_end = line_bytes.len();
+    if line_end > 0 && line_bytes[line_end - 1] == b'\n' {
+        line_end -= 1;
+        if line_end > 0 && line_bytes[line_end - 1] == b'\r' {
+            line_end -= 1;
+        }
+    }
+    &line_bytes[..line_end]
+}
+
+// Find the words of a line that have the Soundex code of the pattern
+// Soundex only knows the letters A to Z, other letters are left out of a word's code
+fn find_soundex_matches(line_text: &str, pattern_code: &str, whole_line: bool) -> Vec<(usize, usize)> {
+    find_matching_words(line_text, whole_line, |word_text| soundex::american_soundex(word_text) == pattern_code)
+}
+
+// Find the words of a line that are at least threshold similar to the pattern
+fn find_similar_words(
+    line_text: &str,
+    search_pattern: &str,
+    ignore_case: bool,
+    whole_line: bool,
+    similarity_metric: SimilarityMetric,
+    threshold: f64,
+) -> Vec<(usize, usize)> {
+    let pattern_to_find = if ignore_case { search_pattern.to_lowercase() } else { search_pattern.to_string() };
+    find_matching_words(line_text, whole_line, |word_text| {
+        let word_text = if ignore_case { word_text.to_lowercase() } else { word_text.to_string() };
+        let similarity = match similarity_metric {
+            SimilarityMetric::Levenshtein => strsim::normalized_levenshtein(&word_text, &pattern_to_find),
+            SimilarityMetric::Jaro => strsim::jaro(&word_text, &pattern_to_find),
+            SimilarityMetric::JaroWinkler { prefix_scale, max_prefix_length } => {
+                jaro_winkler_similarity(&word_text, &pattern_to_find, prefix_scale, max_prefix_length)
+            }
+            SimilarityMetric::Hamming => match strsim::hamming(&word_text, &pattern_to_find) {
+                Ok(distance) => 1.0 - distance as f64 / pattern_to_find.chars().count().max(1) as f64,
+                // Words of another length never match
+                Err(_) => return false,
+            },
+        };
+        similarity >= threshold
+    })
+}
+
+// Jaro-Winkler with an adjustable prefix scale (strsim::jaro_winkler always uses 0.1)
+// Like strsim, only scores above 0.7 get the prefix boost
+fn jaro_winkler_similarity(word_text: &str, pattern_text: &str, prefix_scale: f64, max_prefix_length: usize) -> f64 {
+    let jaro_similarity = strsim::jaro(word_text, pattern_text);
+    if jaro_similarity <= 0.7 {
+        return jaro_similarity;
+    }
+    let prefix_length = word_text.chars().zip(pattern_text.chars()).take(max_prefix_length).take_while(|(word_char, pattern_char)| word_char == pattern_char).count();
+    jaro_similarity + prefix_scale * prefix_length as f64 * (1.0 - jaro_similarity)
+}
+
+// Report the words of a line (runs of letters) for which is_match is true
+fn find_matching_words(line_text: &str, whole_line: bool, is_match: impl Fn(&str) -> bool) -> Vec<(usize, usize)> {
+    let mut match_positions = Vec::new();
+    let mut word_start = None;
+    for (char_offset, line_char) in line_text.char_indices().chain([(line_text.len(), ' ')]) {
+        match (word_start, line_char.is_alphabetic()) {
+            (None, true) => word_start = Some(char_offset),
+            (Some(start_offset), false) => {
+                if is_match(&line_text[start_offset..char_offset]) {
+                    match_positions.push((start_offset, char_offset));
+                }
+                word_start = None;
+            }
+            _ => {}
+        }
+    }
+
+    // -x the line has to be a single matching word
+    if whole_line {
+        match_positions.retain(|&(match_start, match_end)| match_start == 0 && match_end == line_text.len());
+    }
+    match_positions
+}
+
+// Parse --hex-pattern, two hex digits per byte
+fn parse_hex_pattern(hex_text: &str) -> Option<Vec<u8>> {
+    if hex_text.is_empty() || !hex_text.len().is_multiple_of(2) || !hex_text.bytes().all(|hex_digit| hex_digit.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..hex_text.len()).step_by(2).map(|digit_position| u8::from_str_radix(&hex_text[digit_position..digit_position + 2], 16).ok()).collect()
+}
+
+// Find non-overlapping occurrences of the --hex-pattern bytes in a raw line,
+// as offsets into the text that line_from_bytes (and --print-null-match) makes of it
+// A match that covers part of a character covers the whole character in the text
+fn find_hex_matches(raw_line: &[u8], pattern_bytes: &[u8], whole_line: bool, escape_nul_bytes: bool) -> Vec<(usize, usize)> {
+    let raw_content = strip_line_ending(raw_line);
+
+    let mut raw_matches = Vec::new();
+    let mut search_start = 0;
+    while let Some(match_offset) = raw_content[search_start..].windows(pattern_bytes.len()).position(|window| window == pattern_bytes) {
+        let match_start = search_start + match_offset;
+        raw_matches.push((match_start, match_start + pattern_bytes.len()));
+        search_start = match_start + pattern_bytes.len();
+    }
+    if whole_line {
+        raw_matches.retain(|&(match_start, match_end)| match_start == 0 && match_end == raw_content.len());
+    }
+    raw_matches
+        .into_iter()
+        .map(|(match_start, match_end)| {
+            (text_offset(raw_content, match_start, false, escape_nul_bytes), text_offset(raw_content, match_end, true, escape_nul_bytes))
+        })
+        .collect()
+}
+
+// Where a byte offset of a raw line ends up in its lossy UTF-8 text: every invalid sequence
+// becomes one U+FFFD (3 bytes), and with --print-null-match every NUL becomes \0 (2 bytes)
+// Offsets inside a character or an invalid sequence move to its start, or its end with round_up
+fn text_offset(raw_content: &[u8], raw_offset: usize, round_up: bool, escape_nul_bytes: bool) -> usize {
+    let escaped_length = |valid_text: &str| valid_text.len() + if escape_nul_bytes { valid_text.matches('\0').count() } else { 0 };
+    let mut raw_position = 0;
+    let mut text_position = 0;
+    for utf8_chunk in raw_content.utf8_chunks() {
+        let valid_text = utf8_chunk.valid();
+        if raw_offset <= raw_position + valid_text.len() {
+            let mut chunk_offset = raw_offset - raw_position;
+            while !valid_text.is_char_boundary(chunk_offset) {
+                if round_up {
+                    chunk_offset += 1;
+                } else {
+                    chunk_offset -= 1;
+                }
+            }
+            return text_position + escaped_length(&valid_text[..chunk_offset]);
+        }
+        raw_position += valid_text.len();
+        text_position += escaped_length(valid_text);
+
+        let invalid_length = utf8_chunk.invalid().len();
+        if raw_offset < raw_position + invalid_length {
+            let inside_sequence = raw_offset > raw_position;
+            return text_position + if inside_sequence && round_up { char::REPLACEMENT_CHARACTER.len_utf8() } else { 0 };
+        }
+        raw_position += invalid_length;
+        text_position += char::REPLACEMENT_CHARACTER.len_utf8();
+    }
+    text_position
+}
+
+// The other way round from text_offset: where a byte offset of the lossy UTF-8 text was in the raw line
+// Offsets inside a U+FFFD or an escaped \0 move to the start of its raw bytes, or their end with round_up
+fn raw_offset(raw_content: &[u8], text_offset: usize, round_up: bool, escape_nul_bytes: bool) -> usize {
+    let mut raw_position = 0;
+    let mut text_position = 0;
+    for utf8_chunk in raw_content.utf8_chunks() {
+        for raw_byte in utf8_chunk.valid().bytes() {
+            let text_length = if escape_nul_bytes && raw_byte == 0 { 2 } else { 1 };
+            if text_offset < text_position + text_length {
+                let inside_escape = text_offset > text_position;
+                return raw_position + usize::from(inside_escape && round_up);
+            }
+            raw_position += 1;
+            text_position += text_length;
+        }
+
+        let invalid_length = utf8_chunk.invalid().len();
+        if invalid_length > 0 && text_offset < text_position + char::REPLACEMENT_CHARACTER.len_utf8() {
+            let inside_replacement = text_offset > text_position;
+            return raw_position + if inside_replacement && round_up { invalid_length } else { 0 };
+        }
+        if invalid_length > 0 {
+            raw_position += invalid_length;
+            text_position += char::REPLACEMENT_CHARACTER.len_utf8();
+        }
+    }
+    raw_position
+}
+
+// Find non-overlapping substrings within max_distance edits of the pattern
+// At every position the closest window of pattern length +- max_distance characters wins,
+// so this takes O(line length x pattern length) distance computations
+fn find_fuzzy_matches(
+    line_text: &str,
+    search_pattern: &str,
+    ignore_case: bool,
+    whole_line: bool,
+    max_distance: usize,
+    max_matches: usize,
+) -> Vec<(usize, usize)> {
+    let (search_text, pattern_to_find) = if ignore_case {
+        (line_text.to_ascii_lowercase(), search_pattern.to_ascii_lowercase())
+    } else {
+        (line_text.to_string(), search_pattern.to_string())
+    };
+    if whole_line {
+        let is_whole_line_match = strsim::levenshtein(&search_text, &pattern_to_find) <= max_distance;
+        return if is_whole_line_match { vec![(0, line_text.len())] } else { Vec::new() };
+    }
+
+    // ASCII lowercasing keeps every character in place, so offsets in search_text are offsets in the line
+    let char_offsets: Vec<usize> = search_text.char_indices().map(|(char_offset, _)| char_offset).chain([search_text.len()]).collect();
+    let char_count = char_offsets.len() - 1;
+    let pattern_length = pattern_to_find.chars().count();
+    let shortest_window = pattern_length.saturating_sub(max_distance).max(1);
+    let longest_window = pattern_length + max_distance;
+
+    // The closest window starting at a position, the longest one among equally close windows
+    let best_window_at = |window_start: usize| -> Option<(usize, usize)> {
+        let mut best_window: Option<(usize, usize)> = None;
+        for window_length in shortest_window..=longest_window.min(char_count - window_start) {
+            let window_text = &search_text[char_offsets[window_start]..char_offsets[window_start + window_length]];
+            let distance = strsim::levenshtein(window_text, &pattern_to_find);
+            if distance <= max_distance && best_window.is_none_or(|(best_distance, _)| distance <= best_distance) {
+                best_window = Some((distance, window_length));
+            }
+        }
+        best_window
+    };
+
+    let mut match_positions = Vec::new();
+    let mut window_start = 0;
+    while window_start + shortest_window <= char_count && match_positions.len() < max_matches {
+        let Some(mut best_match) = best_window_at(window_start).map(|(distance, window_length)| (distance, window_start, window_length)) else {
+            window_start += 1;
+            continue;
+        };
+        // A closer match may start a few characters later, e.g. "color" in " color"
+        for later_start in window_start + 1..=(window_start + max_distance).min(char_count - shortest_window) {
+            if let Some((distance, window_length)) = best_window_at(later_start)
+                && distance < best_match.0
+            {
+                best_match = (distance, later_start, window_length);
+            }
+        }
+        let (_, match_start, match_length) = best_match;
+        match_positions.push((char_offsets[match_start], char_offsets[match_start + match_length]));
+        window_start = match_start + match_length;
+    }
+    match_positions
+}
+
+// Find all matches of a pattern in a line of text
+// Returns a vector of (start, end) byte positions for each match found
+// With a capture group, the positions are those of the group inside each match
+// With whole_line (-x), a literal pattern only matches a line that is exactly the pattern,
+// regex engines are already anchored when they are compiled
+// The search stops after max_matches matches (None finds them all)
+fn find_matches_in_line(
+    line_text: &str,
+    search_pattern: &str,
+    ignore_case: bool,
+    whole_line: bool,
+    search_engine: &SearchEngine,
+    max_matches: Option<usize>,
+) -> Vec<(usize, usize)> {
+    let max_matches = max_matches.unwrap_or(usize::MAX);
+    match search_engine {
+        SearchEngine::Literal => {}
+        SearchEngine::Soundex { pattern_code } => {
+            let mut match_positions = find_soundex_matches(line_text, pattern_code, whole_line);
+            match_positions.truncate(max_matches);
+            return match_positions;
+        }
+        SearchEngine::WordList(word_automaton) => {
+            let is_word_char = |word_char: char| word_char.is_alphanumeric() || word_char == '_';
+            let mut word_candidates: Vec<(usize, usize)> = word_automaton
+                .find_overlapping_iter(line_text)
+                .map(|found_match| (found_match.start(), found_match.end()))
+                .filter(|&(match_start, match_end)| {
+                    // Only whole words: no word character right before or after the match
+                    !line_text[..match_start].chars().next_back().is_some_and(is_word_char)
+                        && !line_text[match_end..].chars().next().is_some_and(is_word_char)
+                })
+                .filter(|&(match_start, match_end)| !whole_line || (match_start == 0 && match_end == line_text.len()))
+                .collect();
+            // The boundary check comes first, so "foo" is still found in "foo-bars" when "foo-bar" is listed too
+            // Of the whole words left, the longest at the leftmost position wins and hides the ones it overlaps
+            word_candidates.sort_by_key(|&(match_start, match_end)| (match_start, std::cmp::Reverse(match_end)));
+            let mut match_positions: Vec<(usize, usize)> = Vec::new();
+            for (match_start, match_end) in word_candidates {
+                if match_positions.len() == max_matches {
+                    break;
+                }
+                if match_positions.last().is_none_or(|&(_, previous_end)| match_start >= previous_end) {
+                    match_positions.push((match_start, match_end));
+                }
+            }
+            return match_positions;
+        }
+        SearchEngine::Base64(encoded_automaton) => {
+            return encoded_automaton
+                .find_iter(line_text)
+                .map(|found_match| (found_match.start(), found_match.end()))
+                .filter(|&(match_start, match_end)| !whole_line || (match_start == 0 && match_end == line_text.len()))
+                .take(max_matches)
+                .collect();
+        }
+        SearchEngine::HexBytes(pattern_bytes) => {
+            let mut match_positions = find_hex_matches(line_text.as_bytes(), pattern_bytes, whole_line, false);
+            match_positions.truncate(max_matches);
+            return match_positions;
+        }
+        SearchEngine::Similarity { similarity_metric, threshold } => {
+            let mut match_positions = find_similar_words(line_text, search_pattern, ignore_case, whole_line, *similarity_metric, *threshold);
+            match_positions.truncate(max_matches);
+            return match_positions;
+        }
+        SearchEngine::Fuzzy { max_distance } => {
+            return find_fuzzy_matches(line_text, search_pattern, ignore_case, whole_line, *max_distance, max_matches);
+        }
+        SearchEngine::BasicRegexp(compiled_regex) | SearchEngine::ExtendedRegexp(compiled_regex) => {
+            // Case sensitivity is already part of the compiled regex
+            return compiled_regex
+                .find_iter(line_text)
+                .map(|found_match| (found_match.start(), found_match.end()))
+                .take(max_matches)
+                .collect();
+        }
+        SearchEngine::CaptureRegexp { compiled_regex, capture_group } => {
+            // Matches where the group did not take part are skipped
+            return compiled_regex
+                .captures_iter(line_text)
+                .filter_map(|captures| captures.name(capture_group))
+                .map(|group_match| (group_match.start(), group_match.end()))
+                .take(max_matches)
+                .collect();
+        }
+        #[cfg(feature = "pcre2")]
+        SearchEngine::Pcre2 { pcre2_regex, capture_group } => {
+            // Case sensitivity is already part of the compiled regex
+            // Stop at the first matching error (e.g. the PCRE2 match limit was hit)
+            if let Some(group_name) = capture_group {
+                // Matches where the group did not take part are skipped
+                return pcre2_regex
+                    .captures_iter(line_text.as_bytes())
+                    .map_while(Result::ok)
+                    .filter_map(|captures| captures.name(group_name))
+                    .map(|group_match| (group_match.start(), group_match.end()))
+                    .take(max_matches)
+                    .collect();
+            }
+            return pcre2_regex
+                .find_iter(line_text.as_bytes())
+                .map_while(Result::ok)
+                .map(|found_match| (found_match.start(), found_match.end()))
+                .take(max_matches)
+                .collect();
+        }
+    }
+
+    if whole_line {
+        let is_whole_line_match = if ignore_case { line_text.eq_ignore_ascii_case(search_pattern) } else { line_text == search_pattern };
+        return if is_whole_line_match { vec![(0, line_text.len())] } else { Vec::new() };
+    }
+
+    let mut match_positions = Vec::new();
+    if search_pattern.is_empty() {
+        return match_positions;
+    }
+
+    // convert to lowercase if ignore_case is true
+    let (search_text, pattern_to_find) = if ignore_case {
+        (line_text.to_ascii_lowercase(), search_pattern.to_ascii_lowercase())
+    } else {
+        (line_text.to_string(), search_pattern.to_string())
+    };
+
+    let text_bytes = search_text.as_bytes();
+    let pattern_bytes = pattern_to_find.as_bytes();
+
+    // pattern cannot be empty or longer than text
+    if pattern_bytes.is_empty() || pattern_bytes.len() > text_bytes.len() {
+        return match_positions;
+    }
+
+    // Search through the text from left to right
+    let mut current_position = 0;
+    let pattern_length = pattern_bytes.len();
+
+    while current_position + pattern_length <= text_bytes.len() && match_positions.len() < max_matches {
+        // Compare current slice of text with the pattern
+        let current_slice = &text_bytes[current_position..current_position + pattern_length];
+
+        if current_slice == pattern_bytes {
+            // Find a match! Record the start and end positions
+            let match_start = current_position;
+            let match_end = current_position + pattern_length;
+            match_positions.push((match_start, match_end));
+            current_position += pattern_length;
+        } else {
+            // No match found, move to next position
+            current_position += 1;
+        }
+    }
+
+    match_positions
+}
+
+// Header row printed before any --csv results
+const CSV_HEADER: [&str; 6] = ["file", "line_number", "column", "match_start", "match_end", "text"];
+// Header rows of --csv with -l / -L and with --count, which print a row per file instead of per match
+const CSV_FILE_HEADER: [&str; 1] = ["file"];
+const CSV_COUNT_HEADER: [&str; 2] = ["file", "count"];
+
+// The --csv header row that fits the rows printed with the other options
+fn csv_header(config: &Config) -> &'static [&'static str] {
+    if config.count_matches {
+        &CSV_COUNT_HEADER
+    } else if config.files_with_matches || config.files_without_match {
+        &CSV_FILE_HEADER
+    } else {
+        &CSV_HEADER
+    }
+}
+
+// Render a file for --csv without its lines: the file of -l and -L, or the file and its count with --count
+fn render_csv_file(file_path: &Path, selected_line_count: Option<usize>) -> String {
+    let file_name = file_path.display().to_string();
+    let mut csv_writer = csv::Writer::from_writer(Vec::new());
+    let _ = match selected_line_count {
+        Some(selected_line_count) => csv_writer.write_record([file_name, selected_line_count.to_string()]),
+        None => csv_writer.write_record([file_name]),
+    };
+    csv_record_text(csv_writer)
+}
+
+// Render a line for --csv, one row per match
+// match_start and match_end are byte offsets in the line, column is the 1-based character column
+// Lines without matches (-v) get a single row with empty match fields
+fn render_csv_rows(file_path: &Path, line_content: &str, matches_found: &[(usize, usize)], line_number: usize) -> String {
+    let file_name = file_path.display().to_string();
+    let line_number = line_number.to_string();
+    let mut csv_writer = csv::Writer::from_writer(Vec::new());
+
+    if matches_found.is_empty() {
+        let _ = csv_writer.write_record([file_name.as_str(), &line_number, "", "", "", line_content]);
+    }
+    for &(match_start, match_end) in matches_found {
+        let column = line_content[..match_start].chars().count() + 1;
+        let _ = csv_writer.write_record([
+            file_name.as_str(),
+            &line_number,
+            &column.to_string(),
+            &match_start.to_string(),
+            &match_end.to_string(),
+            line_content,
+        ]);
+    }
+    csv_record_text(csv_writer)
+}
+
+// Take the rows written so far out of a CSV writer
+fn csv_record_text(csv_writer: csv::Writer<Vec<u8>>) -> String {
+    let csv_bytes = csv_writer.into_inner().unwrap_or_default();
+    String::from_utf8_lossy(&csv_bytes).into_owned()
+}
+
+// Render a line for --template, the template is evaluated once per match
+// Lines without matches (-v) are evaluated once with empty match fields
+fn render_template_matches(
+    template_env: &minijinja::Environment<'static>,
+    file_path: &Path,
+    line_content: &str,
+    matches_found: &[(usize, usize)],
+    line_number: usize,
+) -> String {
+    // read_template_file compiled it already, this only looks it up
+    let output_template = match template_env.get_template(TEMPLATE_NAME) {
+        Ok(output_template) => output_template,
+        Err(template_error) => {
+            eprintln!("grep: --template: {}", template_error);
+            return String::new();
+        }
+    };
+    let file_name = file_path.display().to_string();
+    let mut match_fields: Vec<(Option<usize>, Option<usize>, Option<usize>)> = matches_found
+        .iter()
+        .map(|&(match_start, match_end)| {
+            let column = line_content[..match_start].chars().count() + 1;
+            (Some(column), Some(match_start), Some(match_end))
+        })
+        .collect();
+    if match_fields.is_empty() {
+        match_fields.push((None, None, None));
+    }
+
+    let mut template_text = String::new();
+    for (column, match_start, match_end) in match_fields {
+        let result = minijinja::context! {
+            file => &file_name,
+            line_number => line_number,
+            column => column,
+            match_start => match_start,
+            match_end => match_end,
+            text => line_content,
+        };
+        match output_template.render(minijinja::context! { result }) {
+            Ok(rendered_text) => template_text.push_str(&rendered_text),
+            Err(render_error) => eprintln!("grep: --template: {}", render_error),
+        }
+        template_text.push('\n');
+    }
+    template_text
+}
+
+// Render a line for --xml, one <match> element per match
+// Lines without matches (-v) get a single element without a column
+fn render_xml_matches(line_content: &str, matches_found: &[(usize, usize)], line_number: usize) -> String {
+    let escaped_text = quick_xml::escape::escape(line_content);
+    if matches_found.is_empty() {
+        return format!("    <match line=\"{}\"><text>{}</text></match>\n", line_number, escaped_text);
+    }
+
+    let mut xml_text = String::new();
+    for &(match_start, _) in matches_found {
+        let column = line_content[..match_start].chars().count() + 1;
+        xml_text.push_str(&format!(
+            "    <match line=\"{}\" col=\"{}\"><text>{}</text></match>\n",
+            line_number, column, escaped_text
+        ));
+    }
+    xml_text
+}
+
+// Render a file for --xml without its lines: the file of -l and -L, or its count with --count
+fn render_xml_file(file_path: &Path, selected_line_count: Option<usize>) -> String {
+    let file_name = quick_xml::escape::escape(file_path.display().to_string());
+    match selected_line_count {
+        Some(selected_line_count) => format!("  <file name=\"{}\" count=\"{}\"/>\n", file_name, selected_line_count),
+        None => format!("  <file name=\"{}\"/>\n", file_name),
+    }
+}
+
+// Render a line for --pretty: a fixed-width line number column and the highlighted line,
+// wrapped to the terminal width with continuation lines indented under the text
+fn render_pretty_line(line_content: &str, matches_found: &[(usize, usize)], line_number: usize, config: &Config) -> String {
+    const LINE_NUMBER_WIDTH: usize = 6;
+    // The line number column is followed by ": "
+    let text_indent = LINE_NUMBER_WIDTH + 2;
+    let terminal_width = config
+        .column_width
+        .unwrap_or_else(|| terminal_size::terminal_size().map_or(80, |(terminal_size::Width(width), _)| usize::from(width)));
+    let text_width = terminal_width.saturating_sub(text_indent).max(10);
+
+    let line_number_text = format!("{:>width$}", line_number, width = LINE_NUMBER_WIDTH);
+    let mut rendered_line = format!("{}{} ", paint(&line_number_text, &config.color_palette.line_number_color), format_separator(":", config));
+    for (chunk_index, &(chunk_start, chunk_end)) in wrap_line_ranges(line_content, text_width).iter().enumerate() {
+        if chunk_index > 0 {
+            rendered_line.push('\n');
+            rendered_line.push_str(&" ".repeat(text_indent));
+        }
+        let chunk_matches = clip_matches(matches_found, chunk_start, chunk_end);
+        rendered_line.push_str(&colorize_hits(&line_content[chunk_start..chunk_end], &chunk_matches, config));
+    }
+    rendered_line.push('\n');
+    rendered_line
+}
+
+// Render a line for --column-width: the prefix (file name, line number) is only printed
+// on the first line, continuation lines are indented to where the text starts
+fn render_wrapped_line(line_prefix: &str, line_content: &str, matches_found: &[(usize, usize)], column_width: usize, config: &Config) -> String {
+    let text_indent = visible_width(line_prefix);
+    let text_width = column_width.saturating_sub(text_indent).max(1);
+
+    let mut rendered_line = line_prefix.to_string();
+    for (chunk_index, &(chunk_start, chunk_end)) in wrap_line_ranges(line_content, text_width).iter().enumerate() {
+        if chunk_index > 0 {
+            rendered_line.push('\n');
+            rendered_line.push_str(&" ".repeat(text_indent));
+        }
+        let chunk_text = &line_content[chunk_start..chunk_end];
+        if config.colored_output {
+            let chunk_matches = clip_matches(matches_found, chunk_start, chunk_end);
+            rendered_line.push_str(&colorize_hits(chunk_text, &chunk_matches, config));
+        } else {
+            rendered_line.push_str(chunk_text);
+        }
+    }
+    rendered_line.push('\n');
+    rendered_line
+}
+
+// --hex-output render a line like hexdump -C, offsets count from the start of the line
+// The file name and line number prefix (if any) goes on its own line above the dump
+fn render_hex_dump(line_prefix: &str, line_bytes: &[u8], matches_found: &[(usize, usize)], config: &Config) -> String {
+    const BYTES_PER_ROW: usize = 16;
 
-    // Search each file
-    // If a file can't be read, skip it
-    for file_path in files_to_search {
-        let _ = search_file(&file_path, &config);
+    let mut rendered_dump = String::new();
+    if !line_prefix.is_empty() {
+        rendered_dump.push_str(line_prefix);
+        rendered_dump.push('\n');
     }
-}
\ No newline at end of file
+    let highlight = |byte_position: usize, byte_text: String| {
+        let is_matched = matches_found.iter().any(|&(match_start, match_end)| (match_start..match_end).contains(&byte_position));
+        if config.colored_output && is_matched { colorize_hits(&byte_text, &[(0, byte_text.len())], config) } else { byte_text }
+    };
+
+    // An empty line still gets a row, so that it shows up in the output
+    let mut dump_rows: Vec<&[u8]> = line_bytes.chunks(BYTES_PER_ROW).collect();
+    if dump_rows.is_empty() {
+        dump_rows.push(&[]);
+    }
+    for (row_index, row_bytes) in dump_rows.into_iter().enumerate() {
+        let row_offset = row_index * BYTES_PER_ROW;
+        rendered_dump.push_str(&format!("{:08x} ", row_offset));
+        let mut ascii_text = String::new();
+        for column in 0..BYTES_PER_ROW {
+            // An extra space between the two halves of the row
+            if column % 8 == 0 {
+                rendered_dump.push(' ');
+            }
+            let Some(&byte) = row_bytes.get(column) else {
+                rendered_dump.push_str("   ");
+                continue;
+            };
+            rendered_dump.push_str(&highlight(row_offset + column, format!("{:02x}", byte)));
+            rendered_dump.push(' ');
+            let shown_char = if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' };
+            ascii_text.push_str(&highlight(row_offset + column, shown_char.to_string()));
+        }
+        rendered_dump.push_str(&format!(" |{}|\n", ascii_text));
+    }
+    rendered_dump
+}
+
+// Render the part of a line left by --max-columns, followed by ... to show that it was cut
+// Wrapping with --column-width applies to the shortened line
+fn render_truncated_line(
+    line_prefix: &str,
+    shown_text: &str,
+    shown_matches: &[(usize, usize)],
+    column_width: Option<usize>,
+    config: &Config,
+) -> String {
+    let mut rendered_line = match column_width {
+        Some(column_width) => render_wrapped_line(line_prefix, shown_text, shown_matches, column_width, config),
+        None if config.colored_output => format!("{}{}\n", line_prefix, colorize_hits(shown_text, shown_matches, config)),
+        None => format!("{}{}\n", line_prefix, shown_text),
+    };
+    rendered_line.pop();
+    rendered_line.push_str("...\n");
+    rendered_line
+}
+
+// Count the characters of text that take up space on the terminal,
+// skipping color codes (ESC [ ... m) and hyperlinks (ESC ] ... ESC \)
+fn visible_width(text: &str) -> usize {
+    let mut text_chars = text.chars().peekable();
+    let mut char_count = 0;
+    while let Some(text_char) = text_chars.next() {
+        if text_char != '\x1b' {
+            char_count += 1;
+            continue;
+        }
+        match text_chars.next() {
+            Some('[') => {
+                // The sequence ends with a letter
+                for sequence_char in text_chars.by_ref() {
+                    if sequence_char.is_ascii_alphabetic() {
+                        break;
+                    }
+                }
+            }
+            Some(']') => {
+                // The sequence ends with ESC \ or BEL
+                while let Some(sequence_char) = text_chars.next() {
+                    if sequence_char == '\x07' {
+                        break;
+                    }
+                    if sequence_char == '\x1b' && text_chars.peek() == Some(&'\\') {
+                        text_chars.next();
+                        break;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    char_count
+}
+
+// Split a line into byte ranges of at most max_chars characters
+// Lines are broken after the last whitespace of a range if there is one
+fn wrap_line_ranges(line_text: &str, max_chars: usize) -> Vec<(usize, usize)> {
+    let mut line_ranges = Vec::new();
+    let mut chunk_start = 0;
+    let mut chunk_chars = 0;
+    let mut last_break_position = None;
+
+    for (char_position, line_char) in line_text.char_indices() {
+        if chunk_chars == max_chars {
+            let chunk_end = last_break_position.unwrap_or(char_position);
+            line_ranges.push((chunk_start, chunk_end));
+            // Characters after the break point move on to the next range
+            chunk_chars = line_text[chunk_end..char_position].chars().count();
+            chunk_start = chunk_end;
+            last_break_position = None;
+        }
+        chunk_chars += 1;
+        if line_char.is_whitespace() {
+            last_break_position = Some(char_position + line_char.len_utf8());
+        }
+    }
+    line_ranges.push((chunk_start, line_text.len()));
+    line_ranges
+}
+
+// Restrict match ranges to the part of a line between chunk_start and chunk_end,
+// shifting them so that they are relative to chunk_start
+fn clip_matches(match_ranges: &[(usize, usize)], chunk_start: usize, chunk_end: usize) -> Vec<(usize, usize)> {
+    match_ranges
+        .iter()
+        .filter_map(|&(match_start, match_end)| {
+            let clipped_start = match_start.max(chunk_start);
+            let clipped_end = match_end.min(chunk_end);
+            (clipped_start < clipped_end).then(|| (clipped_start - chunk_start, clipped_end - chunk_start))
+        })
+        .collect()
+}
+
+// Add color (red by default) and the --underline style to matched text segments,
+// with --highlight-line the whole line is colored as well
+fn colorize_hits(original_line: &str, match_ranges: &[(usize, usize)], config: &Config) -> String {
+    if match_ranges.is_empty() {
+        // -v and --print-non-matching-lines lines have no matches, but still get the line background
+        if config.highlight_line {
+            return original_line.on_yellow().to_string();
+        }
+        return original_line.to_string();
+    }
+
+    // Create a new string to build the colored result
+    let mut colored_result = String::with_capacity(original_line.len() + match_ranges.len() * 10);
+    let mut last_processed_position = 0;
+
+    for &(match_start, match_end) in match_ranges {
+        // Add the normal text before the current match
+        if match_start > last_processed_position {
+            let normal_text_before_match = &original_line[last_processed_position..match_start];
+            colored_result.push_str(normal_text_before_match);
+        }
+
+        // Add match color
+        let matched_text_segment = &original_line[match_start..match_end];
+        let mut match_colored_text = match &config.color_palette.match_color {
+            Some(match_color) => paint(matched_text_segment, match_color),
+            None => matched_text_segment.to_string(),
+        };
+        if config.underline_match {
+            match_colored_text = match_colored_text.underline().to_string();
+        }
+        if config.highlight_line {
+            match_colored_text = match_colored_text.bold().to_string();
+        }
+        colored_result.push_str(&match_colored_text);
+        last_processed_position = match_end;
+    }
+
+    // Add remaining text after the last match
+    if last_processed_position < original_line.len() {
+        let remaining_text = &original_line[last_processed_position..];
+        colored_result.push_str(remaining_text);
+    }
+
+    // --highlight-line the background is restored after each match's reset code
+    if config.highlight_line {
+        return colored_result.on_yellow().to_string();
+    }
+    colored_result
+}
+
+// Throttles file opens for --rate-limit
+// A token bucket holding a single token: one file may be opened every interval
+struct RateLimiter {
+    open_interval: Duration,
+    next_token_time: Instant,
+}
+
+impl RateLimiter {
+    fn new(files_per_second: u64) -> RateLimiter {
+        RateLimiter {
+            open_interval: Duration::from_secs_f64(1.0 / files_per_second as f64),
+            next_token_time: Instant::now(),
+        }
+    }
+
+    // Sleep until the next token is available, then take it
+    fn wait_for_token(&mut self) {
+        let now = Instant::now();
+        if self.next_token_time > now {
+            thread::sleep(self.next_token_time - now);
+        }
+        // Unused time does not pile up, the bucket never holds more than one token
+        self.next_token_time = self.next_token_time.max(now) + self.open_interval;
+    }
+}
+
+// Forwards every write to two writers, used by --tee for the output file and stdout
+struct TeeWriter {
+    first_writer: Box<dyn Write>,
+    second_writer: Box<dyn Write>,
+}
+
+impl Write for TeeWriter {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        // Both writers must receive the same bytes, so never report a partial write
+        self.first_writer.write_all(buffer)?;
+        self.second_writer.write_all(buffer)?;
+        Ok(buffer.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.first_writer.flush()?;
+        self.second_writer.flush()
+    }
+}
+
+// Start the pager with a pipe as its stdin, arguments are split on whitespace
+fn spawn_pager(pager_command: &str) -> io::Result<Child> {
+    let mut command_parts = pager_command.split_whitespace();
+    let program = command_parts.next().unwrap_or_default();
+    Command::new(program).args(command_parts).stdin(Stdio::piped()).spawn()
+}
+
+// Totals of a whole search, used for the exit status and --count-files
+#[derive(Debug, Default)]
+struct SearchSummary {
+    listed_any_file: bool,
+    searched_file_count: usize,
+    // A file could not be read, grep exits with 2
+    had_read_error: bool,
+    // The results could not be written, the search was stopped there
+    write_error: Option<io::Error>,
+}
+
+impl SearchSummary {
+    // Count a searched file, -L lists it when none of its lines was selected
+    // A binary file skipped by -I has no name printed, so it is not listed
+    fn record_searched_file(&mut self, found_selected_line: Option<bool>) {
+        self.listed_any_file |= found_selected_line == Some(false);
+        self.searched_file_count += 1;
+    }
+
+    // Report a file that could not be read, -s only keeps the message off stderr
+    fn record_read_error(&mut self, file_path: &Path, read_error: &io::Error, config: &Config) {
+        if !config.suppress_errors {
+            eprintln!("grep: {}: {}", file_path.display(), read_error);
+        }
+        self.had_read_error = true;
+    }
+}
+
+// Search each file one after another
+// If a file can't be read, report it and go on with the next one
+fn search_files(files_to_search: impl Iterator<Item = CollectedFile>, config: &Config, printer: &mut ResultPrinter) -> SearchSummary {
+    let mut search_summary = SearchSummary::default();
+    let mut rate_limiter = config.rate_limit.map(RateLimiter::new);
+    for collected_file in files_to_search {
+        let file_path = match collected_file {
+            Ok(file_path) => file_path,
+            Err((error_path, read_error)) => {
+                search_summary.record_read_error(&error_path, &read_error, config);
+                continue;
+            }
+        };
+        if config.list_files {
+            // --files only shows what would be searched
+            if let Err(write_error) = print_file_name(&file_path, config, printer) {
+                search_summary.write_error = Some(write_error);
+                break;
+            }
+            search_summary.searched_file_count += 1;
+            continue;
+        }
+        if let Some(rate_limiter) = rate_limiter.as_mut() {
+            rate_limiter.wait_for_token();
+        }
+        match search_file(&file_path, config, printer) {
+            Ok(found_selected_line) => search_summary.record_searched_file(found_selected_line),
+            // Nothing more can be printed, the error is not about this file
+            Err(write_error) if printer.has_write_failed() => {
+                search_summary.write_error = Some(write_error);
+                break;
+            }
+            Err(read_error) => search_summary.record_read_error(&file_path, &read_error, config),
+        }
+    }
+    search_summary
+}
+
+// Read a whole file with tokio, so that slow reads can wait at the same time
+#[cfg(feature = "tokio")]
+async fn read_file_async(file_path: PathBuf, input_limit: Option<u64>, suppress_errors: bool) -> io::Result<Vec<u8>> {
+    use tokio::io::AsyncReadExt;
+
+    let file = tokio::fs::File::open(&file_path).await?;
+    let mut reader = tokio::io::BufReader::new(file);
+    let mut file_content = Vec::new();
+    let Some(input_limit) = input_limit else {
+        reader.read_to_end(&mut file_content).await?;
+        return Ok(file_content);
+    };
+
+    // One byte more than the limit tells whether the file is longer, like --multiline-limit
+    reader.take(input_limit + 1).read_to_end(&mut file_content).await?;
+    if file_content.len() as u64 > input_limit {
+        file_content.truncate(input_limit as usize);
+        if !suppress_errors {
+            warn_input_limit(&file_path, input_limit);
+        }
+    }
+    Ok(file_content)
+}
+
+// A searched file as sent by search_stream
+// file_index is the position of the file in the paths given, results arrive in the order they are finished
+struct SearchResult {
+    file_index: usize,
+    file_path: PathBuf,
+    // What the search of the file printed, still to be emitted by the receiver
+    output_records: Vec<OutputRecord>,
+    // Whether a line was selected, like search_file
+    search_outcome: io::Result<Option<bool>>,
+}
+
+// Search the files on one thread per CPU core and send every result as soon as it is ready
+// The threads take the next path from the shared paths receiver, so a slow file doesn't hold up the others
+// They stop when the paths run out or when the result receiver is dropped
+fn search_stream(paths: mpsc::Receiver<(usize, PathBuf)>, config: Arc<Config>) -> mpsc::Receiver<SearchResult> {
+    let thread_count = thread::available_parallelism().map_or(1, |thread_count| thread_count.get());
+    let work_queue = Arc::new(Mutex::new(paths));
+    let (result_sender, result_receiver) = mpsc::channel();
+    for _ in 0..thread_count {
+        let work_queue = Arc::clone(&work_queue);
+        let config = Arc::clone(&config);
+        let result_sender = result_sender.clone();
+        thread::spawn(move || {
+            loop {
+                // The lock is only held to take the path, the other threads take theirs during the search
+                let next_file = work_queue.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).recv();
+                let Ok((file_index, file_path)) = next_file else {
+                    break;
+                };
+                let mut file_printer = ResultPrinter::collecting();
+                let search_outcome = search_file(&file_path, &config, &mut file_printer);
+                let search_result = SearchResult { file_index, file_path, output_records: file_printer.into_records(), search_outcome };
+                // The receiver is gone, nobody wants the rest of the results
+                if result_sender.send(search_result).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+    result_receiver
+}
+
+// --parallel search the files with search_stream and print the results in the order of the files
+// The files are collected on a thread of their own while the first ones are searched already,
+// at most MAX_QUEUED_FILES of them wait to be searched or to be printed
+fn search_parallel(files_to_search: impl Iterator<Item = CollectedFile> + Send, config: &Arc<Config>, printer: &mut ResultPrinter) -> SearchSummary {
+    const MAX_QUEUED_FILES: usize = 256;
+
+    thread::scope(|collecting_scope| {
+        let mut search_summary = SearchSummary::default();
+        let (path_sender, path_receiver) = mpsc::sync_channel(MAX_QUEUED_FILES);
+        // Every collected file in order, the paths that could not be collected included
+        let (collected_sender, collected_receiver) = mpsc::sync_channel(MAX_QUEUED_FILES);
+        collecting_scope.spawn(move || {
+            let mut file_index = 0;
+            for collected_file in files_to_search {
+                if let Ok(file_path) = &collected_file {
+                    if path_sender.send((file_index, file_path.clone())).is_err() {
+                        break;
+                    }
+                    file_index += 1;
+                }
+                // The printing stopped, the rest of the tree doesn't need to be walked
+                if collected_sender.send(collected_file).is_err() {
+                    break;
+                }
+            }
+        });
+        let search_results = search_stream(path_receiver, Arc::clone(config));
+
+        // Results that arrived before the ones of earlier files
+        let mut early_results = std::collections::HashMap::new();
+        let mut next_file_index = 0;
+        for collected_file in &collected_receiver {
+            // Paths that could not be collected are reported in their place, like search_files does
+            if let Err((error_path, read_error)) = collected_file {
+                search_summary.record_read_error(&error_path, &read_error, config);
+                continue;
+            }
+            let search_result = loop {
+                if let Some(search_result) = early_results.remove(&next_file_index) {
+                    break Some(search_result);
+                }
+                // Every thread stopped before sending this file, only possible if one of them panicked
+                let Ok(search_result) = search_results.recv() else {
+                    break None;
+                };
+                early_results.insert(search_result.file_index, search_result);
+            };
+            next_file_index += 1;
+            let Some(search_result) = search_result else {
+                search_summary.had_read_error = true;
+                continue;
+            };
+
+            // The search threads only collect records, so a failed write can only happen here
+            let emit_outcome = search_result.output_records.into_iter().try_for_each(|output_record| printer.emit_record(output_record));
+            if let Err(write_error) = emit_outcome {
+                search_summary.write_error = Some(write_error);
+                break;
+            }
+            match search_result.search_outcome {
+                Ok(found_selected_line) => search_summary.record_searched_file(found_selected_line),
+                Err(read_error) => search_summary.record_read_error(&search_result.file_path, &read_error, config),
+            }
+        }
+        // Dropping the receivers stops the collecting thread and the search threads, the scope waits for the former
+        drop(collected_receiver);
+        drop(search_results);
+        search_summary
+    })
+}
+
+// --async read files concurrently in tokio tasks, but search and print them in
+// argument order so the output is the same as with search_files
+// At most MAX_PENDING_READS files are held in memory at once
+#[cfg(feature = "tokio")]
+fn main_async(files_to_search: impl Iterator<Item = CollectedFile>, config: &Config, printer: &mut ResultPrinter) -> SearchSummary {
+    const MAX_PENDING_READS: usize = 64;
+
+    let runtime = match tokio::runtime::Runtime::new() {
+        Ok(runtime) => runtime,
+        Err(runtime_error) => {
+            eprintln!("grep: failed to start the async runtime: {}", runtime_error);
+            process::exit(2);
+        }
+    };
+
+    runtime.block_on(async {
+        let mut search_summary = SearchSummary::default();
+        let mut rate_limiter = config.rate_limit.map(RateLimiter::new);
+        let mut remaining_files = files_to_search;
+        let mut pending_reads = std::collections::VecDeque::new();
+
+        loop {
+            while pending_reads.len() < MAX_PENDING_READS
+                && let Some(collected_file) = remaining_files.next()
+            {
+                // Paths that could not be collected wait in line, so errors are reported in order too
+                let read_task = collected_file.map(|file_path| {
+                    if let Some(rate_limiter) = rate_limiter.as_mut() {
+                        rate_limiter.wait_for_token();
+                    }
+                    let read_task = tokio::spawn(read_file_async(file_path.clone(), config.input_limit, config.suppress_errors));
+                    (file_path, read_task)
+                });
+                pending_reads.push_back(read_task);
+            }
+
+            let Some(pending_read) = pending_reads.pop_front() else {
+                break;
+            };
+            let (file_path, read_task) = match pending_read {
+                Ok(pending_read) => pending_read,
+                Err((error_path, read_error)) => {
+                    search_summary.record_read_error(&error_path, &read_error, config);
+                    continue;
+                }
+            };
+            // If a file can't be read, report it and go on with the next one
+            let search_result = match read_task.await {
+                Ok(Ok(file_content)) => search_reader(io::Cursor::new(file_content), &file_path, config, printer),
+                Ok(Err(read_error)) => Err(read_error),
+                Err(join_error) => Err(io::Error::other(join_error)),
+            };
+            match search_result {
+                Ok(found_selected_line) => search_summary.record_searched_file(found_selected_line),
+                Err(write_error) if printer.has_write_failed() => {
+                    search_summary.write_error = Some(write_error);
+                    break;
+                }
+                Err(read_error) => search_summary.record_read_error(&file_path, &read_error, config),
+            }
+        }
+        search_summary
+    })
+}
+
+// Create or truncate the output file, or append to it with --append-output
+fn open_output_file(output_path: &Path, append_output: bool) -> io::Result<File> {
+    if append_output {
+        OpenOptions::new().create(true).append(true).open(output_path)
+    } else {
+        File::create(output_path)
+    }
+}
+
+fn main() {
+    // The error has already been printed, exit with 2 like grep does for bad usage
+    // Shared with the search threads of --parallel
+    let config = match parse_arguments() {
+        Ok(config) => Arc::new(config),
+        Err(_) => {
+            process::exit(2);
+        }
+    };
+
+    // The colored crate checks for a terminal and NO_COLOR itself,
+    // --color=always and --color=never have to override that decision
+    match config.color_mode {
+        ColorMode::Always => colored::control::set_override(true),
+        ColorMode::Never => colored::control::set_override(false),
+        ColorMode::Auto => {}
+    }
+
+    // A read from an unresponsive file can block forever, so the timeout
+    // is enforced from a separate thread instead of inside the search loop
+    if let Some(timeout) = config.timeout {
+        thread::spawn(move || {
+            thread::sleep(timeout);
+            eprintln!("grep: timeout of {:?} reached, aborting search", timeout);
+            process::exit(2);
+        });
+    }
+
+    // --pager everything meant for stdout goes to the pager instead
+    let mut pager_process = match &config.pager {
+        Some(pager_command) => match spawn_pager(pager_command) {
+            Ok(pager_process) => Some(pager_process),
+            Err(spawn_error) => {
+                eprintln!("grep: failed to start pager '{}': {}", pager_command, spawn_error);
+                process::exit(2);
+            }
+        },
+        None => None,
+    };
+    let stdout_writer: Box<dyn Write> = match pager_process.as_mut().and_then(|pager_process| pager_process.stdin.take()) {
+        Some(pager_input) => Box::new(pager_input),
+        None => Box::new(io::stdout()),
+    };
+
+    // Open the output file once, before any searching is done
+    // --append-output keeps the results of earlier runs, --tee also copies them to stdout
+    let output_writer: Box<dyn Write> = match &config.output_file {
+        Some(output_path) => match open_output_file(output_path, config.append_output) {
+            Ok(output_file) if config.tee_output => Box::new(TeeWriter {
+                first_writer: Box::new(BufWriter::new(output_file)),
+                second_writer: stdout_writer,
+            }),
+            Ok(output_file) => Box::new(BufWriter::new(output_file)),
+            Err(open_error) => {
+                eprintln!("grep: {}: {}", output_path.display(), open_error);
+                process::exit(2);
+            }
+        },
+        None => stdout_writer,
+    };
+
+    // Convert user-provided paths into the files to search, searched as they are found
+    let files_to_search = collect_files(&config, &DefaultFilter);
+
+    let mut printer = ResultPrinter::new(output_writer, config.sort_output);
+    // --xml the document is opened before the first file and closed after the last one
+    if config.xml_output && !config.list_files {
+        let _ = printer.emit(Path::new(""), 0, "", "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<grep>\n".to_string());
+    }
+    if config.csv_output && !config.list_files {
+        // The empty file name keeps the header first when results are sorted
+        let mut csv_writer = csv::Writer::from_writer(Vec::new());
+        let _ = csv_writer.write_record(csv_header(&config));
+        let _ = printer.emit(Path::new(""), 0, "", csv_record_text(csv_writer));
+    }
+    #[cfg(feature = "tokio")]
+    let mut search_summary = if config.async_io && !config.list_files {
+        main_async(files_to_search, &config, &mut printer)
+    } else if config.parallel_search && !config.list_files {
+        search_parallel(files_to_search, &config, &mut printer)
+    } else {
+        search_files(files_to_search, &config, &mut printer)
+    };
+    #[cfg(not(feature = "tokio"))]
+    let mut search_summary = if config.parallel_search && !config.list_files {
+        search_parallel(files_to_search, &config, &mut printer)
+    } else {
+        search_files(files_to_search, &config, &mut printer)
+    };
+
+    if config.xml_output && !config.list_files {
+        let _ = printer.emit(Path::new(""), 0, "", "</grep>\n".to_string());
+    }
+
+    // --count-files goes after the results, or to stderr so that it stays out of a --files listing
+    // and out of the --xml and --csv documents
+    let mut summary_text = None;
+    if config.count_files {
+        if config.list_files || config.xml_output || config.csv_output {
+            eprintln!("{}", search_summary.searched_file_count);
+        } else {
+            summary_text = Some(format!("{}\n", search_summary.searched_file_count));
+        }
+    }
+    // A write that failed during the search already ended it, the rest can't be written either
+    let write_result = match search_summary.write_error.take() {
+        Some(write_error) => {
+            // Close the pager's stdin as finish() would, so that waiting for it below can't hang
+            drop(printer);
+            Err(write_error)
+        }
+        None => printer.finish(summary_text),
+    };
+    // Quitting the pager early (or a reader like head) closes the pipe, which is not an error
+    if let Err(write_error) = write_result
+        && write_error.kind() != io::ErrorKind::BrokenPipe
+    {
+        eprintln!("grep: failed to write results: {}", write_error);
+        process::exit(2);
+    }
+
+    // --count-bytes stays out of the results, like the timing of a profiler
+    if config.count_bytes {
+        eprintln!("grep: {} bytes read", BYTES_READ.load(Ordering::Relaxed));
+    }
+
+    // finish() dropped the pager's stdin, so the pager sees the end of its input
+    // Wait for it so the user can keep reading before the shell prompt returns
+    if let Some(mut pager_process) = pager_process {
+        let _ = pager_process.wait();
+    }
+
+    // A file that could not be read makes the search fail, even with -s
+    if search_summary.had_read_error {
+        process::exit(2);
+    }
+
+    // -L succeeds only if at least one file was listed
+    // If every file had a selected line there is no output, so exit with 1
+    if config.files_without_match && !search_summary.listed_any_file {
+        process::exit(1);
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_regexp_literal_needs_the_whole_line() {
+        assert!(find_matches_in_line("hello world", "hello", false, true, &SearchEngine::Literal, None).is_empty());
+        assert_eq!(find_matches_in_line("hello", "hello", false, true, &SearchEngine::Literal, None), vec![(0, 5)]);
+    }
+
+    #[test]
+    fn line_regexp_regex_needs_the_whole_line() {
+        let anchored_regex = compile_regex(&anchor_to_line("hello", true), false, RegexOptions::default()).unwrap();
+        assert!(!anchored_regex.is_match("hello world"));
+        assert!(anchored_regex.is_match("hello"));
+
+        let search_engine = SearchEngine::ExtendedRegexp(anchored_regex);
+        assert!(find_matches_in_line("hello world", "hello", false, true, &search_engine, None).is_empty());
+        assert_eq!(find_matches_in_line("hello", "hello", false, true, &search_engine, None), vec![(0, 5)]);
+    }
+
+    #[test]
+    fn line_regexp_keeps_alternation_inside_the_anchors() {
+        let anchored_regex = compile_regex(&anchor_to_line("hello|world", true), false, RegexOptions::default()).unwrap();
+        assert!(!anchored_regex.is_match("hello world"));
+        assert!(anchored_regex.is_match("world"));
+    }
+
+    #[test]
+    fn only_look_around_and_back_references_need_pcre2() {
+        assert!(needs_pcre2("foo(?=bar)"));
+        assert!(needs_pcre2("(?<!x)y"));
+        assert!(needs_pcre2("(a)\\1"));
+        assert!(needs_pcre2("(?<word>a)\\k<word>"));
+        assert!(!needs_pcre2("(?<word>a)b"));
+        assert!(!needs_pcre2("a\\\\1"));
+        assert!(!needs_pcre2("a.c[0-9]+"));
+    }
+
+    #[test]
+    fn highlight_line_colors_lines_without_matches() {
+        colored::control::set_override(true);
+        let config = Config { colored_output: true, highlight_line: true, ..Config::default() };
+        assert_eq!(colorize_hits("no match here", &[], &config), "no match here".on_yellow().to_string());
+
+        let config = Config { colored_output: true, ..Config::default() };
+        assert_eq!(colorize_hits("no match here", &[], &config), "no match here");
+    }
+
+    // Collects what a ResultPrinter writes, it owns its writer so the buffer is shared
+    #[derive(Clone, Default)]
+    struct SharedOutput(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+    impl Write for SharedOutput {
+        fn write(&mut self, written_bytes: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(written_bytes)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn sort_by_match_keeps_file_headers_with_their_results() {
+        let shared_output = SharedOutput::default();
+        let mut printer = ResultPrinter::new(Box::new(shared_output.clone()), Some(SortBy::Match));
+        let (first_file, second_file) = (Path::new("a.txt"), Path::new("b.txt"));
+        printer.emit_file_header(first_file, "a.txt\n".to_string()).unwrap();
+        printer.emit(first_file, 1, "zeta", "1:zeta\n".to_string()).unwrap();
+        printer.emit(first_file, 2, "beta", "2:beta\n".to_string()).unwrap();
+        printer.emit_file_header(second_file, "b.txt\n".to_string()).unwrap();
+        printer.emit(second_file, 1, "alpha", "1:alpha\n".to_string()).unwrap();
+        printer.finish(None).unwrap();
+
+        let printed = String::from_utf8(shared_output.0.borrow().clone()).unwrap();
+        assert_eq!(printed, "b.txt\n1:alpha\n\na.txt\n2:beta\n1:zeta\n");
+    }
+
+    #[test]
+    fn raw_offset_undoes_the_utf8_replacement_and_the_nul_escape() {
+        // Each invalid byte becomes a U+FFFD of its own in the text
+        let raw_content = b"abc\xff\xfedef";
+        assert_eq!(raw_offset(raw_content, 3, false, false), 3);
+        assert_eq!(raw_offset(raw_content, 6, false, false), 4);
+        assert_eq!(raw_offset(raw_content, 9, false, false), 5);
+        assert_eq!(raw_offset(raw_content, 12, false, false), 8);
+        assert_eq!(raw_offset(raw_content, 4, true, false), 4);
+        assert_eq!(raw_offset(raw_content, 4, false, false), 3);
+
+        // x\\0y is the --print-null-match text of x NUL y
+        let raw_content = b"x\0y";
+        assert_eq!(raw_offset(raw_content, 3, false, true), 2);
+        assert_eq!(raw_offset(raw_content, 2, false, true), 1);
+        assert_eq!(raw_offset(raw_content, 2, true, true), 2);
+        assert_eq!(raw_offset(raw_content, 4, false, true), 3);
+    }
+
+    #[test]
+    fn basic_regexp_swaps_the_escaped_and_the_literal_operators() {
+        assert_eq!(translate_basic_regexp(r"a\{2\}b").unwrap(), "a{2}b");
+        assert_eq!(translate_basic_regexp("a{2}b").unwrap(), r"a\{2\}b");
+        assert_eq!(translate_basic_regexp(r"\(ab\)\{2\}").unwrap(), "(ab){2}");
+        assert_eq!(translate_basic_regexp(r"a\|b").unwrap(), "a|b");
+        assert_eq!(translate_basic_regexp("a|b+c?").unwrap(), r"a\|b\+c\?");
+
+        let repeated_group = regex::Regex::new(&translate_basic_regexp(r"^\(ab\)\{2\}$").unwrap()).unwrap();
+        assert!(repeated_group.is_match("abab"));
+        assert!(!repeated_group.is_match("ab"));
+    }
+
+    #[test]
+    fn basic_regexp_anchors_and_star_are_literal_in_the_middle() {
+        assert_eq!(translate_basic_regexp("*a").unwrap(), r"\*a");
+        assert_eq!(translate_basic_regexp(r"\(*a\)").unwrap(), r"(\*a)");
+        assert_eq!(translate_basic_regexp("a*").unwrap(), "a*");
+        assert_eq!(translate_basic_regexp("^a$").unwrap(), "^a$");
+        assert_eq!(translate_basic_regexp("a^b$c").unwrap(), r"a\^b\$c");
+        assert_eq!(translate_basic_regexp(r"a$\|^b").unwrap(), "a$|^b");
+    }
+
+    #[test]
+    fn basic_regexp_bracket_expressions_keep_posix_meaning() {
+        assert_eq!(translate_basic_regexp("[]]x").unwrap(), r"[\]]x");
+        assert_eq!(translate_basic_regexp("[^]a]").unwrap(), r"[^\]a]");
+        assert_eq!(translate_basic_regexp(r"[\d]").unwrap(), r"[\\d]");
+        assert_eq!(translate_basic_regexp("[[:digit:]x]").unwrap(), "[[:digit:]x]");
+
+        let backslash_or_d = regex::Regex::new(&translate_basic_regexp(r"^[\d]$").unwrap()).unwrap();
+        assert!(backslash_or_d.is_match("\\"));
+        assert!(backslash_or_d.is_match("d"));
+        assert!(!backslash_or_d.is_match("5"));
+    }
+
+    #[test]
+    fn basic_regexp_rejects_what_it_can_not_translate() {
+        assert_eq!(translate_basic_regexp(r"a\"), Err("trailing backslash".to_string()));
+        assert_eq!(translate_basic_regexp("[ab"), Err("unmatched [".to_string()));
+        assert_eq!(translate_basic_regexp("[]"), Err("unmatched [".to_string()));
+        assert_eq!(translate_basic_regexp(r"\(a\)\1"), Err("back-references are not supported".to_string()));
+    }
+
+    #[test]
+    fn fuzzy_matches_stay_within_the_edit_distance() {
+        assert_eq!(find_fuzzy_matches("the color red", "color", false, false, 0, usize::MAX), vec![(4, 9)]);
+        assert!(find_fuzzy_matches("the colour red", "color", false, false, 0, usize::MAX).is_empty());
+        // Of the equally close windows the longest one wins
+        assert_eq!(find_fuzzy_matches("the colour red", "color", false, false, 1, usize::MAX), vec![(4, 10)]);
+        assert!(find_fuzzy_matches("the culoar red", "color", false, false, 1, usize::MAX).is_empty());
+        assert_eq!(find_fuzzy_matches("the culoar red", "color", false, false, 2, usize::MAX), vec![(4, 10)]);
+    }
+
+    #[test]
+    fn fuzzy_matches_with_ignore_case_and_whole_line() {
+        assert!(find_fuzzy_matches("COLOR", "color", false, false, 0, usize::MAX).is_empty());
+        assert_eq!(find_fuzzy_matches("COLOR", "color", true, false, 0, usize::MAX), vec![(0, 5)]);
+
+        assert_eq!(find_fuzzy_matches("colour", "color", false, true, 1, usize::MAX), vec![(0, 6)]);
+        assert!(find_fuzzy_matches("my colour", "color", false, true, 1, usize::MAX).is_empty());
+        assert_eq!(find_fuzzy_matches("Colour", "color", true, true, 1, usize::MAX), vec![(0, 6)]);
+    }
+
+    #[test]
+    fn fuzzy_pattern_longer_than_the_line() {
+        assert!(find_fuzzy_matches("ab", "color", false, false, 0, usize::MAX).is_empty());
+        assert!(find_fuzzy_matches("col", "color", false, false, 1, usize::MAX).is_empty());
+        assert_eq!(find_fuzzy_matches("col", "color", false, false, 2, usize::MAX), vec![(0, 3)]);
+        assert!(find_fuzzy_matches("", "color", false, false, 2, usize::MAX).is_empty());
+    }
+
+    #[test]
+    fn permission_filter_parses_octal_modes() {
+        assert_eq!(parse_permission_filter("644"), Some(PermissionFilter::Exact(0o644)));
+        assert_eq!(parse_permission_filter("0755"), Some(PermissionFilter::Exact(0o755)));
+        assert_eq!(parse_permission_filter("4755"), Some(PermissionFilter::Exact(0o4755)));
+        assert_eq!(parse_permission_filter("17777"), None);
+        assert_eq!(parse_permission_filter("648"), None);
+    }
+
+    #[test]
+    fn permission_filter_parses_symbolic_modes() {
+        assert_eq!(parse_permission_filter("u+x"), Some(PermissionFilter::Symbolic { set_bits: 0o100, unset_bits: 0 }));
+        assert_eq!(parse_permission_filter("go-w"), Some(PermissionFilter::Symbolic { set_bits: 0, unset_bits: 0o022 }));
+        // Without a who the clause is about everyone
+        assert_eq!(parse_permission_filter("+x"), Some(PermissionFilter::Symbolic { set_bits: 0o111, unset_bits: 0 }));
+        assert_eq!(
+            parse_permission_filter("u+rw,o-w"),
+            Some(PermissionFilter::Symbolic { set_bits: 0o600, unset_bits: 0o002 })
+        );
+    }
+
+    #[test]
+    fn permission_filter_rejects_other_modes() {
+        // = would need the other bits cleared too, it is not supported
+        assert_eq!(parse_permission_filter("u=rw"), None);
+        assert_eq!(parse_permission_filter(""), None);
+        assert_eq!(parse_permission_filter("u+"), None);
+        assert_eq!(parse_permission_filter("x+r"), None);
+        assert_eq!(parse_permission_filter("u+z"), None);
+        assert_eq!(parse_permission_filter("u+x,"), None);
+    }
+
+    #[test]
+    fn base64_forms_keep_only_the_characters_of_the_pattern() {
+        // The characters shared with the bytes around the pattern, and the padding, are trimmed
+        assert_eq!(base64_forms("secret"), vec!["c2VjcmV0", "NlY3Jld", "zZWNyZX"]);
+        // A single byte has no whole character of its own at offset 1
+        assert_eq!(base64_forms("a").len(), 2);
+        assert!(base64_forms("").is_empty());
+    }
+
+    #[test]
+    fn base64_forms_find_the_pattern_at_every_offset() {
+        use base64::Engine;
+
+        for leading_text in ["", "x", "xy"] {
+            let encoded_text = base64::engine::general_purpose::STANDARD.encode(format!("{}secret!", leading_text));
+            assert!(
+                base64_forms("secret").iter().any(|encoded_form| encoded_text.contains(encoded_form.as_str())),
+                "no form found in {}",
+                encoded_text
+            );
+        }
+        let encoded_text = base64::engine::general_purpose::STANDARD.encode("xsecrat!");
+        assert!(!base64_forms("secret").iter().any(|encoded_form| encoded_text.contains(encoded_form.as_str())));
+    }
+
+    #[test]
+    fn match_search_stops_at_the_match_limit() {
+        assert_eq!(find_matches_in_line("a a a a", "a", false, false, &SearchEngine::Literal, Some(2)), vec![(0, 1), (2, 3)]);
+        assert_eq!(find_matches_in_line("a a a a", "a", false, false, &SearchEngine::Literal, None).len(), 4);
+
+        let search_engine = SearchEngine::ExtendedRegexp(compile_regex("a+", false, RegexOptions::default()).unwrap());
+        assert_eq!(find_matches_in_line("a aa aaa", "a+", false, false, &search_engine, Some(1)), vec![(0, 1)]);
+
+        assert_eq!(find_fuzzy_matches("color colour culor", "color", false, false, 1, 2), vec![(0, 5), (6, 12)]);
+    }
+
+    #[test]
+    fn palette_color_picks_the_cube_or_the_gray_ramp() {
+        assert_eq!(nearest_palette_color(255, 100, 0), 202);
+        assert_eq!(nearest_palette_color(0, 0, 0), 16);
+        assert_eq!(nearest_palette_color(128, 128, 128), 244);
+    }
+}
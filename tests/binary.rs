@@ -0,0 +1,44 @@
+mod common;
+
+use common::run_grep_on;
+
+// A NUL byte after the first line, but still within the first 8 KB
+fn nul_in_first_block() -> Vec<u8> {
+    let mut file_content = b"hello text\n".to_vec();
+    file_content.extend_from_slice(&b"filler line\n".repeat(100));
+    file_content.extend_from_slice(b"hello \0 binary\n");
+    file_content
+}
+
+#[test]
+fn nul_in_the_first_block_makes_the_whole_file_binary() {
+    let (exit_code, printed) = run_grep_on("binary-first-block", nul_in_first_block(), &["hello"]);
+    assert_eq!(exit_code, Some(0));
+    assert!(printed.starts_with("Binary file ") && printed.ends_with(" matches\n"), "{:?}", printed);
+
+    let (_, printed) = run_grep_on("binary-first-block-skip", nul_in_first_block(), &["-I", "hello"]);
+    assert_eq!(printed, "");
+}
+
+#[test]
+fn nul_in_the_first_block_is_found_when_the_file_is_read_at_once() {
+    let (_, printed) = run_grep_on("binary-multiline", nul_in_first_block(), &["-U", "-E", "--multiline-limit", "16", "hello"]);
+    assert!(printed.starts_with("Binary file "), "{:?}", printed);
+}
+
+#[test]
+fn skip_binary_content_checks_each_later_block_before_its_lines() {
+    // The first 8 KB are text, the NUL byte is at the end of the second block
+    let mut file_content = b"hello first\n".to_vec();
+    file_content.extend_from_slice(&b"filler line\n".repeat(1000));
+    file_content.extend_from_slice(b"hello second\n");
+    file_content.extend_from_slice(&b"filler line\n".repeat(300));
+    file_content.extend_from_slice(b"\0\n");
+
+    let (_, printed) = run_grep_on("binary-streaming", &file_content, &["--skip-binary-content", "hello"]);
+    assert!(printed.starts_with("hello first\nBinary file ") && printed.ends_with(" matches\n"), "{:?}", printed);
+    let (_, printed) = run_grep_on("binary-streaming-skip", &file_content, &["--skip-binary-content", "-I", "hello"]);
+    assert_eq!(printed, "hello first\n");
+    let (_, printed) = run_grep_on("binary-streaming-text", &file_content, &["hello"]);
+    assert_eq!(printed, "hello first\nhello second\n");
+}
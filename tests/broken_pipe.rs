@@ -0,0 +1,41 @@
+use std::fs;
+use std::io::Read;
+use std::process::{Command, Stdio};
+
+// Start grep on enough matching lines to fill the pipe, read the first bytes and close the pipe
+fn run_grep_closing_stdout(test_name: &str, grep_arguments: &[&str]) -> (Option<i32>, String) {
+    let test_directory = std::env::temp_dir().join(format!("grep-{}-{}", test_name, std::process::id()));
+    let _ = fs::remove_dir_all(&test_directory);
+    fs::create_dir_all(&test_directory).unwrap();
+    let file_content: String = (0..3000).map(|line_number| format!("match {}\n", line_number)).collect();
+    for file_number in 0..50 {
+        fs::write(test_directory.join(format!("file{:02}.txt", file_number)), &file_content).unwrap();
+    }
+
+    let mut grep_process = Command::new(env!("CARGO_BIN_EXE_grep"))
+        .args(grep_arguments)
+        .arg(&test_directory)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut first_bytes = [0; 16];
+    grep_process.stdout.take().unwrap().read_exact(&mut first_bytes).unwrap();
+    let output = grep_process.wait_with_output().unwrap();
+    fs::remove_dir_all(&test_directory).unwrap();
+    (output.status.code(), String::from_utf8_lossy(&output.stderr).into_owned())
+}
+
+#[test]
+fn closed_stdout_ends_the_search_quietly() {
+    let (exit_code, error_text) = run_grep_closing_stdout("pipe-closed", &["-r", "match"]);
+    assert_eq!(exit_code, Some(0));
+    assert!(error_text.is_empty(), "unexpected messages: {}", error_text);
+}
+
+#[test]
+fn closed_stdout_ends_a_parallel_search_quietly() {
+    let (exit_code, error_text) = run_grep_closing_stdout("pipe-closed-parallel", &["--parallel", "-r", "match"]);
+    assert_eq!(exit_code, Some(0));
+    assert!(error_text.is_empty(), "unexpected messages: {}", error_text);
+}
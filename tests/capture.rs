@@ -0,0 +1,20 @@
+mod common;
+
+use common::run_grep_on;
+
+#[test]
+fn capture_reports_the_named_group_of_an_extended_regexp() {
+    let file_content = "hello world\nworld\nhello there\n";
+    let (exit_code, printed) = run_grep_on("capture-extended", file_content, &["-E", "--capture", "x", "-c", "--color=always", "(?P<x>wor)ld"]);
+    assert_eq!(exit_code, Some(0));
+    assert_eq!(printed.lines().count(), 2);
+    // Only the group is highlighted, not the whole match
+    assert!(printed.contains("\x1b[31mwor\x1b[0mld"));
+}
+
+#[test]
+fn capture_needs_a_group_of_that_name() {
+    let (exit_code, printed) = run_grep_on("capture-missing", "hello world\n", &["-E", "--capture", "y", "(?P<x>wor)ld"]);
+    assert_eq!(exit_code, Some(2));
+    assert!(printed.is_empty());
+}
@@ -0,0 +1,26 @@
+// Fixtures shared by the integration tests, each test file uses only some of them
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+// Run grep on a single temporary file with the given content, returns the exit code and stdout
+pub fn run_grep_on(test_name: &str, file_content: impl AsRef<[u8]>, grep_arguments: &[&str]) -> (Option<i32>, String) {
+    let test_file = std::env::temp_dir().join(format!("grep-{}-{}.txt", test_name, std::process::id()));
+    fs::write(&test_file, file_content).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_grep")).args(grep_arguments).arg(&test_file).output().unwrap();
+    fs::remove_file(&test_file).unwrap();
+    (output.status.code(), String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+// Write the given files into a fresh directory under the system temp directory
+pub fn write_test_files(test_name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let test_directory = std::env::temp_dir().join(format!("grep-{}-{}", test_name, std::process::id()));
+    let _ = fs::remove_dir_all(&test_directory);
+    fs::create_dir_all(&test_directory).unwrap();
+    for (file_name, file_content) in files {
+        fs::write(test_directory.join(file_name), file_content).unwrap();
+    }
+    test_directory
+}
@@ -0,0 +1,33 @@
+mod common;
+
+use common::run_grep_on;
+
+#[test]
+fn auto_engine_searches_a_regex_pattern_with_the_regex_crate() {
+    let (exit_code, printed) = run_grep_on("engine-auto-regex", "abc\na.c\nxyz\n", &["--engine=auto", "a.c"]);
+    assert_eq!(exit_code, Some(0));
+    assert_eq!(printed, "abc\na.c\n");
+}
+
+#[test]
+fn auto_engine_searches_a_plain_pattern_literally() {
+    let (exit_code, printed) = run_grep_on("engine-auto-literal", "abc\nxyz\n", &["--engine=auto", "xyz"]);
+    assert_eq!(exit_code, Some(0));
+    assert_eq!(printed, "xyz\n");
+}
+
+#[test]
+fn a_pattern_with_metacharacters_is_an_extended_regexp_by_default() {
+    let (_, printed) = run_grep_on("engine-default", "abc\na.c\nac\n", &["a.c|^ac$"]);
+    assert_eq!(printed, "abc\na.c\nac\n");
+    let (_, printed) = run_grep_on("engine-fixed", "abc\na.c\n", &["-F", "a.c"]);
+    assert_eq!(printed, "a.c\n");
+}
+
+#[test]
+fn engine_accepts_the_regex_dialect_names() {
+    let (_, printed) = run_grep_on("engine-extended", "ab\na+b\naab\n", &["--engine=extended", "a+b"]);
+    assert_eq!(printed, "ab\naab\n");
+    let (_, printed) = run_grep_on("engine-basic", "ab\na+b\naab\n", &["--engine=basic", "a+b"]);
+    assert_eq!(printed, "a+b\n");
+}
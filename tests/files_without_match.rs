@@ -0,0 +1,69 @@
+mod common;
+
+use common::{run_grep_on, write_test_files};
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+fn run_files_without_match(test_directory: &Path, file_names: &[&str]) -> (Option<i32>, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_grep"))
+        .arg("-L")
+        .arg("hello")
+        .args(file_names.iter().map(|file_name| test_directory.join(file_name)))
+        .output()
+        .unwrap();
+    (output.status.code(), String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[test]
+fn exits_zero_when_a_file_has_no_match() {
+    let test_directory = write_test_files("l-some", &[("match.txt", "hello world\n"), ("other.txt", "goodbye\n")]);
+    let (exit_code, printed) = run_files_without_match(&test_directory, &["match.txt", "other.txt"]);
+    fs::remove_dir_all(&test_directory).unwrap();
+
+    assert_eq!(exit_code, Some(0));
+    assert!(printed.contains("other.txt"));
+    assert!(!printed.contains("match.txt"));
+}
+
+#[test]
+fn exits_one_when_every_file_matches() {
+    let test_directory = write_test_files("l-none", &[("first.txt", "hello\n"), ("second.txt", "say hello\n")]);
+    let (exit_code, printed) = run_files_without_match(&test_directory, &["first.txt", "second.txt"]);
+    fs::remove_dir_all(&test_directory).unwrap();
+
+    assert_eq!(exit_code, Some(1));
+    assert!(printed.is_empty());
+}
+
+#[test]
+fn exits_two_when_a_file_cannot_be_read() {
+    let test_directory = write_test_files("l-missing", &[("other.txt", "goodbye\n")]);
+    let (exit_code, printed) = run_files_without_match(&test_directory, &["other.txt", "missing.txt"]);
+    fs::remove_dir_all(&test_directory).unwrap();
+
+    // The readable file is still listed, but the search failed
+    assert_eq!(exit_code, Some(2));
+    assert!(printed.contains("other.txt"));
+}
+
+#[test]
+fn an_empty_file_has_no_match() {
+    let test_directory = write_test_files("l-empty", &[("empty.txt", ""), ("match.txt", "hello\n")]);
+    let (exit_code, printed) = run_files_without_match(&test_directory, &["empty.txt", "match.txt"]);
+    fs::remove_dir_all(&test_directory).unwrap();
+
+    assert_eq!(exit_code, Some(0));
+    assert!(printed.contains("empty.txt"));
+    assert!(!printed.contains("match.txt"));
+}
+
+#[test]
+fn a_binary_file_skipped_by_suppress_binary_is_not_listed() {
+    for extra_arguments in [&["-I"][..], &["-I", "--parallel"]] {
+        let grep_arguments: Vec<&str> = ["-L", "hello"].iter().chain(extra_arguments).copied().collect();
+        let (exit_code, printed) = run_grep_on("l-binary", b"goodbye\0binary\n", &grep_arguments);
+        assert_eq!(exit_code, Some(1), "with {:?}", extra_arguments);
+        assert!(printed.is_empty());
+    }
+}
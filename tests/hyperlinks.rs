@@ -0,0 +1,39 @@
+use std::fs;
+use std::process::Command;
+
+// Run grep -l with a terminal that supports links, stdout is a pipe
+fn run_grep_listing(test_name: &str, grep_arguments: &[&str]) -> String {
+    let test_file = std::env::temp_dir().join(format!("grep-{}-{}.txt", test_name, std::process::id()));
+    fs::write(&test_file, "hello world\n").unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_grep"))
+        .env("TERM_PROGRAM", "vscode")
+        .env_remove("NO_COLOR")
+        .args(grep_arguments)
+        .arg("-l")
+        .arg("hello")
+        .arg(&test_file)
+        .output()
+        .unwrap();
+    fs::remove_file(&test_file).unwrap();
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+#[test]
+fn no_links_when_stdout_is_not_a_terminal() {
+    for grep_arguments in [&[][..], &["-c"], &["--color=auto"], &["-c", "--hyperlinks"]] {
+        let printed = run_grep_listing("links-pipe", grep_arguments);
+        assert!(!printed.contains("\x1b]8;;"), "link printed with {:?}: {:?}", grep_arguments, printed);
+    }
+}
+
+#[test]
+fn no_links_with_color_never() {
+    let printed = run_grep_listing("links-never", &["-c", "--color=never", "--hyperlinks"]);
+    assert!(!printed.contains("\x1b]8;;"));
+}
+
+#[test]
+fn links_with_color_always() {
+    let printed = run_grep_listing("links-always", &["--color=always"]);
+    assert!(printed.starts_with("\x1b]8;;file://"));
+}
@@ -0,0 +1,75 @@
+mod common;
+
+use common::run_grep_on;
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+fn run_grep_with_stderr(test_name: &str, file_content: &str, grep_arguments: &[&str]) -> (String, String) {
+    let test_file = std::env::temp_dir().join(format!("grep-{}-{}.txt", test_name, std::process::id()));
+    fs::write(&test_file, file_content).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_grep")).args(grep_arguments).arg(&test_file).output().unwrap();
+    fs::remove_file(&test_file).unwrap();
+    (String::from_utf8_lossy(&output.stdout).into_owned(), String::from_utf8_lossy(&output.stderr).into_owned())
+}
+
+#[test]
+fn input_limit_warns_only_when_the_file_is_longer() {
+    let (printed, error_text) = run_grep_with_stderr("input-limit-longer", "hello\nhello again\n", &["--input-limit", "6", "hello"]);
+    assert_eq!(printed, "hello\n");
+    assert!(error_text.contains("only the first 6 bytes were searched"), "{:?}", error_text);
+
+    let (printed, error_text) = run_grep_with_stderr("input-limit-exact", "hello\n", &["--input-limit", "6", "hello"]);
+    assert_eq!(printed, "hello\n");
+    assert!(error_text.is_empty(), "{:?}", error_text);
+
+    let (_, printed) = run_grep_on("input-limit-quiet", "hello\nhello again\n", &["-s", "--input-limit", "6", "hello"]);
+    assert_eq!(printed, "hello\n");
+}
+
+// The writer keeps the FIFO open, so reading past the limit would wait for it
+#[cfg(unix)]
+#[test]
+fn input_limit_does_not_wait_for_more_of_a_fifo() {
+    let fifo_path = std::env::temp_dir().join(format!("grep-input-limit-fifo-{}", std::process::id()));
+    let _ = fs::remove_file(&fifo_path);
+    assert!(Command::new("mkfifo").arg(&fifo_path).status().unwrap().success());
+
+    let (done_sender, done_receiver) = mpsc::channel::<()>();
+    let writer_path = fifo_path.clone();
+    let fifo_writer = thread::spawn(move || {
+        let mut fifo = fs::OpenOptions::new().write(true).open(&writer_path).unwrap();
+        fifo.write_all(b"hello fifo").unwrap();
+        let _ = done_receiver.recv_timeout(Duration::from_secs(10));
+    });
+
+    let mut grep_process = Command::new(env!("CARGO_BIN_EXE_grep"))
+        .args(["--type", "p", "--input-limit", "10", "hello"])
+        .arg(&fifo_path)
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let started = Instant::now();
+    let finished_in_time = loop {
+        if grep_process.try_wait().unwrap().is_some() {
+            break true;
+        }
+        if started.elapsed() > Duration::from_secs(5) {
+            let _ = grep_process.kill();
+            break false;
+        }
+        thread::sleep(Duration::from_millis(10));
+    };
+    let output = grep_process.wait_with_output().unwrap();
+    // Opening the reading end lets the writer go on, should grep not have opened the FIFO
+    let _fifo_reader = fs::File::open(&fifo_path).unwrap();
+    drop(done_sender);
+    fifo_writer.join().unwrap();
+    fs::remove_file(&fifo_path).unwrap();
+
+    assert!(finished_in_time, "grep waited for the FIFO after the limit");
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "hello fifo\n");
+}
@@ -0,0 +1,26 @@
+use std::fs;
+use std::process::Command;
+
+#[test]
+fn invert_pattern_leaves_out_lines_with_a_listed_string() {
+    let test_directory = std::env::temp_dir().join(format!("grep-invert-pattern-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&test_directory);
+    fs::create_dir_all(&test_directory).unwrap();
+    let pattern_file = test_directory.join("excluded.txt");
+    let searched_file = test_directory.join("log.txt");
+    // Entries are plain strings: "a.c" does not match "abc", comments and blank lines are skipped
+    fs::write(&pattern_file, "# known noise\n  debug  \n\na.c\n").unwrap();
+    fs::write(&searched_file, "error: disk\ndebug error\nerror in a.c\nerror in abc\n").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_grep"))
+        .arg("--invert-pattern")
+        .arg(&pattern_file)
+        .arg("error")
+        .arg(&searched_file)
+        .output()
+        .unwrap();
+    fs::remove_dir_all(&test_directory).unwrap();
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "error: disk\nerror in abc\n");
+}
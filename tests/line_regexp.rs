@@ -0,0 +1,19 @@
+mod common;
+
+use common::run_grep_on;
+
+#[test]
+fn line_regexp_literal_only_selects_whole_lines() {
+    let (exit_code, printed) = run_grep_on("line-regexp-literal", "hello world\nhello\nsay hello\n", &["-x", "hello"]);
+    assert_eq!(exit_code, Some(0));
+    assert_eq!(printed, "hello\n");
+
+    let (_, printed) = run_grep_on("line-regexp-literal-none", "hello world\n", &["--line-regexp", "hello"]);
+    assert_eq!(printed, "");
+}
+
+#[test]
+fn line_regexp_regex_only_selects_whole_lines() {
+    let (_, printed) = run_grep_on("line-regexp-regex", "hello world\nhello\nworld\n", &["-x", "-E", "hello|world"]);
+    assert_eq!(printed, "hello\nworld\n");
+}
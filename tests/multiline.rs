@@ -0,0 +1,33 @@
+mod common;
+
+use common::run_grep_on;
+
+#[test]
+fn multiline_match_prints_every_line_it_covers() {
+    let (exit_code, printed) = run_grep_on("multiline-span", "one foo\nbar two\nfoo\nbaz\n", &["-n", "-U", "-E", "foo\\nbar"]);
+    assert_eq!(exit_code, Some(0));
+    assert_eq!(printed, "1: one foo\n2: bar two\n");
+}
+
+#[test]
+fn without_multiline_newlines_are_never_matched() {
+    let (_, printed) = run_grep_on("multiline-off", "one foo\nbar two\n", &["-E", "foo\\nbar"]);
+    assert!(printed.is_empty());
+}
+
+#[test]
+fn dot_matches_newlines_only_with_multiline_dotall() {
+    let file_content = "begin\nmiddle\nend\nafter\n";
+    let (_, printed) = run_grep_on("multiline-dot", file_content, &["-U", "-E", "begin.*end"]);
+    assert!(printed.is_empty());
+    let (_, printed) = run_grep_on("multiline-dotall", file_content, &["-U", "--multiline-dotall", "-E", "begin.*end"]);
+    assert_eq!(printed, "begin\nmiddle\nend\n");
+}
+
+#[test]
+fn files_over_the_multiline_limit_are_searched_line_by_line() {
+    let file_content = "one foo\nbar two\nbaz\n";
+    let (exit_code, printed) = run_grep_on("multiline-limit", file_content, &["-n", "-U", "--multiline-limit", "10", "-E", "foo\\nbar|baz"]);
+    assert_eq!(exit_code, Some(0));
+    assert_eq!(printed, "3: baz\n");
+}
@@ -0,0 +1,45 @@
+use std::fs;
+use std::process::Command;
+
+#[test]
+fn parallel_results_keep_the_order_of_the_files() {
+    let test_directory = std::env::temp_dir().join(format!("grep-parallel-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&test_directory);
+    fs::create_dir_all(&test_directory).unwrap();
+    let file_paths: Vec<_> = (0..32)
+        .map(|file_number| {
+            let file_path = test_directory.join(format!("file{:02}.txt", file_number));
+            fs::write(&file_path, format!("other line\nhello {}\n", file_number)).unwrap();
+            file_path
+        })
+        .collect();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_grep")).arg("--parallel").arg("hello").args(&file_paths).output().unwrap();
+    fs::remove_dir_all(&test_directory).unwrap();
+
+    let expected_output: String = (0..32).map(|file_number| format!("hello {}\n", file_number)).collect();
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8_lossy(&output.stdout), expected_output);
+}
+
+#[test]
+fn parallel_search_of_a_large_tree_matches_the_sequential_search() {
+    let test_directory = std::env::temp_dir().join(format!("grep-parallel-tree-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&test_directory);
+    for directory_number in 0..4 {
+        let sub_directory = test_directory.join(format!("dir{}", directory_number));
+        fs::create_dir_all(&sub_directory).unwrap();
+        for file_number in 0..200 {
+            fs::write(sub_directory.join(format!("file{:03}.txt", file_number)), format!("hello {} {}\n", directory_number, file_number)).unwrap();
+        }
+    }
+    fs::write(test_directory.join("dir0").join("file999.txt"), "nothing here\n").unwrap();
+
+    let sequential_output = Command::new(env!("CARGO_BIN_EXE_grep")).args(["-r", "hello"]).arg(&test_directory).output().unwrap();
+    let parallel_output = Command::new(env!("CARGO_BIN_EXE_grep")).args(["--parallel", "-r", "hello"]).arg(&test_directory).output().unwrap();
+    fs::remove_dir_all(&test_directory).unwrap();
+
+    assert_eq!(parallel_output.status.code(), Some(0));
+    assert_eq!(String::from_utf8_lossy(&parallel_output.stdout).lines().count(), 800);
+    assert_eq!(parallel_output.stdout, sequential_output.stdout);
+}
@@ -0,0 +1,11 @@
+mod common;
+
+use common::run_grep_on;
+
+#[test]
+fn escaped_nul_bytes_are_found_as_documented() {
+    let (_, printed) = run_grep_on("null-match-fixed", b"a\0b\nc\n", &["--print-null-match", "-F", "\\0"]);
+    assert_eq!(printed, "a\\0b\n");
+    let (_, printed) = run_grep_on("null-match-regex", b"a\0b\nc\n", &["--print-null-match", "-E", "a\\\\0"]);
+    assert_eq!(printed, "a\\0b\n");
+}
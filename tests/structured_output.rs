@@ -0,0 +1,80 @@
+mod common;
+
+use common::write_test_files;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+// Run grep in the test directory, so that the file names in the output are short
+fn run_grep_in(test_directory: &Path, grep_arguments: &[&str]) -> (Option<i32>, String) {
+    let output = Command::new(env!("CARGO_BIN_EXE_grep")).current_dir(test_directory).args(grep_arguments).output().unwrap();
+    (output.status.code(), String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+#[test]
+fn xml_lists_files_as_elements() {
+    let test_directory = write_test_files("xml-list", &[("a.txt", "hello\n"), ("b.txt", "other\n")]);
+    let (_, listed_matching) = run_grep_in(&test_directory, &["--xml", "-l", "hello", "a.txt", "b.txt"]);
+    let (_, listed_other) = run_grep_in(&test_directory, &["--xml", "-L", "hello", "a.txt", "b.txt"]);
+    fs::remove_dir_all(&test_directory).unwrap();
+
+    assert_eq!(listed_matching, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<grep>\n  <file name=\"a.txt\"/>\n</grep>\n");
+    assert_eq!(listed_other, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<grep>\n  <file name=\"b.txt\"/>\n</grep>\n");
+}
+
+#[test]
+fn xml_counts_are_attributes() {
+    let test_directory = write_test_files("xml-count", &[("a.txt", "hello\nhello again\n"), ("b.txt", "other\n")]);
+    let (exit_code, printed) = run_grep_in(&test_directory, &["--xml", "--count-per-file", "hello", "a.txt", "b.txt"]);
+    fs::remove_dir_all(&test_directory).unwrap();
+
+    assert_eq!(exit_code, Some(0));
+    assert_eq!(
+        printed,
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<grep>\n  <file name=\"a.txt\" count=\"2\"/>\n  <file name=\"b.txt\" count=\"0\"/>\n</grep>\n"
+    );
+}
+
+#[test]
+fn csv_lists_files_under_a_file_column() {
+    let test_directory = write_test_files("csv-list", &[("a.txt", "hello\n"), ("b.txt", "other\n")]);
+    let (_, listed_matching) = run_grep_in(&test_directory, &["--csv", "-l", "hello", "a.txt", "b.txt"]);
+    let (_, listed_other) = run_grep_in(&test_directory, &["--csv", "-L", "hello", "a.txt", "b.txt"]);
+    fs::remove_dir_all(&test_directory).unwrap();
+
+    assert_eq!(listed_matching, "file\na.txt\n");
+    assert_eq!(listed_other, "file\nb.txt\n");
+}
+
+#[test]
+fn csv_counts_have_a_count_column() {
+    let test_directory = write_test_files("csv-count", &[("a.txt", "hello\nhello again\n"), ("b.txt", "other\n")]);
+    let (exit_code, printed) = run_grep_in(&test_directory, &["--csv", "--count-per-file", "hello", "a.txt", "b.txt"]);
+    fs::remove_dir_all(&test_directory).unwrap();
+
+    assert_eq!(exit_code, Some(0));
+    assert_eq!(printed, "file,count\na.txt,2\nb.txt,0\n");
+}
+
+#[test]
+fn count_files_stays_out_of_the_xml_and_csv_documents() {
+    let test_directory = write_test_files("structured-count-files", &[("a.txt", "hello\n"), ("b.txt", "other\n")]);
+    let run_counting = |output_format: &str| {
+        Command::new(env!("CARGO_BIN_EXE_grep"))
+            .current_dir(&test_directory)
+            .args([output_format, "--count-files", "-l", "hello", "a.txt", "b.txt"])
+            .output()
+            .unwrap()
+    };
+    let xml_output = run_counting("--xml");
+    let csv_output = run_counting("--csv");
+    fs::remove_dir_all(&test_directory).unwrap();
+
+    assert_eq!(
+        String::from_utf8_lossy(&xml_output.stdout),
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<grep>\n  <file name=\"a.txt\"/>\n</grep>\n"
+    );
+    assert_eq!(String::from_utf8_lossy(&xml_output.stderr), "2\n");
+    assert_eq!(String::from_utf8_lossy(&csv_output.stdout), "file\na.txt\n");
+    assert_eq!(String::from_utf8_lossy(&csv_output.stderr), "2\n");
+}
@@ -0,0 +1,26 @@
+mod common;
+
+use common::write_test_files;
+use std::fs;
+use std::process::Command;
+
+#[test]
+fn a_shorter_listed_word_is_found_when_the_longer_one_is_not_a_whole_word() {
+    let test_directory = write_test_files(
+        "word-list-overlap",
+        &[("words.txt", "foo\nfoo-bar\n"), ("search.txt", "foo-bars x\nfoo-bar y\nfoobar\n")],
+    );
+    let output = Command::new(env!("CARGO_BIN_EXE_grep"))
+        .current_dir(&test_directory)
+        .args(["--color=always", "--word-list", "words.txt", "search.txt"])
+        .output()
+        .unwrap();
+    fs::remove_dir_all(&test_directory).unwrap();
+
+    // foo-bar is cut off by the "s" in the first line, the whole word foo still matches there
+    let printed = String::from_utf8_lossy(&output.stdout);
+    let printed_lines: Vec<&str> = printed.lines().collect();
+    assert_eq!(printed_lines.len(), 2, "{:?}", printed);
+    assert!(printed_lines[0].starts_with("\x1b[31mfoo\x1b[0m-bars x"));
+    assert!(printed_lines[1].starts_with("\x1b[31mfoo-bar\x1b[0m y"));
+}